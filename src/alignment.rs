@@ -0,0 +1,475 @@
+use std::collections::HashSet;
+use std::ops::Range;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_file_dialog::prelude::*;
+use regex::Regex;
+use serde_json::Value;
+
+use crate::editor::{show_and_log_error, show_and_log_info, show_and_log_warning, EditorState};
+use crate::lyrics::{
+  format_syllable_end_timecode, format_timecode, get_displayed_text, get_source_words, TIMECODE_PATTERN
+};
+
+pub struct AlignmentPlugin;
+
+impl Plugin for AlignmentPlugin {
+  fn build(&self, app: &mut App) {
+    app.add_systems(Update, handle_alignment_import_dialog);
+  }
+}
+
+pub struct AlignmentImportDialog;
+
+pub fn configure_file_dialog_plugin(plugin: FileDialogPlugin) -> FileDialogPlugin {
+  plugin.with_load_file::<AlignmentImportDialog>()
+}
+
+// a word ending at least this long before the next one starts gets a syllable
+// end, in seconds
+const END_GAP: f32 = 0.1;
+// how many unmatched words to list in the notification
+const MAX_LISTED_UNMATCHED: usize = 10;
+
+#[derive(Clone, Debug)]
+pub struct AlignedWord {
+  pub text: String,
+  pub start: f32,
+  pub end: f32,
+}
+
+fn handle_alignment_import_dialog(mut events: EventReader<DialogFileLoaded<AlignmentImportDialog>>,
+  mut editor_state: NonSendMut<EditorState>
+) {
+  for ev in events.read() {
+    let contents = String::from_utf8_lossy(&ev.contents);
+    let is_text_grid = ev.path.extension()
+      .is_some_and(|extension| extension.eq_ignore_ascii_case("textgrid"));
+    let parse_result = if is_text_grid {
+      parse_text_grid(&contents)
+    } else {
+      parse_json(&contents)
+    };
+
+    let aligned_words = match parse_result {
+      Ok(aligned_words) => aligned_words,
+      Err(e) => {
+        show_and_log_error(editor_state.as_mut(),
+          format!("Error reading alignment {:?}: {}", ev.path, e));
+        continue;
+      }
+    };
+
+    let Some(project_data) = editor_state.project_data.as_mut() else {
+      continue;
+    };
+    let (lyrics, unmatched_words) = apply_alignment(&project_data.lyrics, &aligned_words);
+    project_data.lyrics = lyrics;
+    editor_state.lyrics_dirty = true;
+    editor_state.needs_save_before_exit = true;
+
+    let matched_count = aligned_words.len() - unmatched_words.len();
+    show_and_log_info(editor_state.as_mut(),
+      format!("Imported timing for {} of {} aligned words", matched_count, aligned_words.len()));
+    if !unmatched_words.is_empty() {
+      let mut listed = unmatched_words.iter()
+        .take(MAX_LISTED_UNMATCHED)
+        .map(|word| format!("{} ({})", word.text, format_timecode(&Duration::from_secs_f32(word.start))))
+        .collect::<Vec<_>>()
+        .join(", ");
+      if unmatched_words.len() > MAX_LISTED_UNMATCHED {
+        listed += &format!(" and {} more", unmatched_words.len() - MAX_LISTED_UNMATCHED);
+      }
+      show_and_log_warning(editor_state.as_mut(),
+        format!("{} aligned words didn't match the lyrics: {}", unmatched_words.len(), listed));
+    }
+  }
+}
+
+/// Reads word timings from Gentle, WhisperX or Montreal Forced Aligner JSON.
+fn parse_json(contents: &str) -> Result<Vec<AlignedWord>, String> {
+  let root: Value = serde_json::from_str(contents).map_err(|e| e.to_string())?;
+
+  let mut aligned_words = Vec::new();
+  if let Some(entries) = root.pointer("/tiers/words/entries").and_then(Value::as_array) {
+    // [start, end, word]
+    for entry in entries {
+      if let (Some(start), Some(end), Some(text)) = (
+        entry.get(0).and_then(Value::as_f64),
+        entry.get(1).and_then(Value::as_f64),
+        entry.get(2).and_then(Value::as_str)
+      ) {
+        aligned_words.push(AlignedWord { text: text.into(), start: start as f32, end: end as f32 });
+      }
+    }
+  } else {
+    collect_json_words(&root, &mut aligned_words);
+  }
+
+  finish_aligned_words(aligned_words)
+}
+
+fn collect_json_words(value: &Value, aligned_words: &mut Vec<AlignedWord>) {
+  match value {
+    Value::Object(object) => {
+      let text = object.get("word").or(object.get("text")).and_then(Value::as_str);
+      let start = object.get("start").and_then(Value::as_f64);
+      let end = object.get("end").and_then(Value::as_f64);
+      // words that couldn't be aligned have no times
+      if let (Some(text), Some(start), Some(end)) = (text, start, end) {
+        // segment-level objects have both text and a list of words, and the
+        // words are more precise
+        if !object.contains_key("words") {
+          aligned_words.push(AlignedWord { text: text.into(), start: start as f32, end: end as f32 });
+          return;
+        }
+      }
+      for child in object.values() {
+        collect_json_words(child, aligned_words);
+      }
+    },
+    Value::Array(array) => {
+      for child in array {
+        collect_json_words(child, aligned_words);
+      }
+    },
+    _ => {}
+  }
+}
+
+/// Reads word timings from the `words` tier of a TextGrid, or its first tier.
+fn parse_text_grid(contents: &str) -> Result<Vec<AlignedWord>, String> {
+  let number_regex = Regex::new(r#"^(xmin|xmax)\s*=\s*([0-9.eE+-]+)"#).unwrap();
+  let text_regex = Regex::new(r#"^text\s*=\s*"(.*)"\s*$"#).unwrap();
+  let name_regex = Regex::new(r#"^name\s*=\s*"(.*)"\s*$"#).unwrap();
+
+  let mut tiers: Vec<(String, Vec<AlignedWord>)> = Vec::new();
+  let mut in_interval = false;
+  let (mut xmin, mut xmax) = (None, None);
+  for line in contents.lines() {
+    let line = line.trim();
+    if line.starts_with("item [") {
+      tiers.push((String::new(), Vec::new()));
+      in_interval = false;
+    } else if line.starts_with("intervals [") {
+      in_interval = true;
+      (xmin, xmax) = (None, None);
+    } else if let Some(captures) = name_regex.captures(line) {
+      if let Some(tier) = tiers.last_mut() {
+        tier.0 = captures[1].to_string();
+      }
+    } else if let Some(captures) = number_regex.captures(line) {
+      let value = captures[2].parse::<f32>().ok();
+      match &captures[1] {
+        "xmin" => xmin = value,
+        _ => xmax = value,
+      }
+    } else if let Some(captures) = text_regex.captures(line) {
+      if let (true, Some(start), Some(end), Some(tier)) = (in_interval, xmin, xmax, tiers.last_mut()) {
+        // quotes are escaped by doubling them
+        tier.1.push(AlignedWord { text: captures[1].replace("\"\"", "\""), start, end });
+      }
+      in_interval = false;
+    }
+  }
+
+  let tier_idx = tiers.iter()
+    .position(|(name, _)| name.eq_ignore_ascii_case("words"))
+    .unwrap_or(0);
+  if tier_idx >= tiers.len() {
+    return Err("no interval tiers found".into());
+  }
+  finish_aligned_words(tiers.swap_remove(tier_idx).1)
+}
+
+/// Drops silences and duplicate words, and sorts the words by time.
+fn finish_aligned_words(mut aligned_words: Vec<AlignedWord>) -> Result<Vec<AlignedWord>, String> {
+  let mut seen = HashSet::new();
+  aligned_words.retain(|word| {
+    let normalized = normalize_word(&word.text);
+    !normalized.is_empty() && !matches!(normalized.as_str(), "sil" | "sp" | "spn" | "eps")
+      && seen.insert((word.text.clone(), word.start.to_bits(), word.end.to_bits()))
+  });
+  aligned_words.sort_by(|a, b| a.start.total_cmp(&b.start));
+  if aligned_words.is_empty() {
+    return Err("no timed words found".into());
+  }
+  Ok(aligned_words)
+}
+
+/// Lowercases a word and strips punctuation for matching.
+fn normalize_word(word: &str) -> String {
+  word.chars()
+    .filter(|c| c.is_alphanumeric())
+    .flat_map(char::to_lowercase)
+    .collect()
+}
+
+/// Gets the byte range of each block in the lyrics source.
+fn get_block_ranges(lyrics: &str) -> Vec<Range<usize>> {
+  let mut block_ranges: Vec<Range<usize>> = Vec::new();
+  let mut in_block = false;
+  let mut line_start = 0;
+  for line in lyrics.split('\n') {
+    let line_end = line_start + line.len();
+    if line.trim().is_empty() {
+      in_block = false;
+    } else if in_block {
+      block_ranges.last_mut().unwrap().end = line_end;
+    } else {
+      block_ranges.push(line_start..line_end);
+      in_block = true;
+    }
+    line_start = line_end + 1;
+  }
+  block_ranges
+}
+
+/// Times the blocks with matched words from the aligned words. Returns the new lyrics and the
+/// words that weren't matched.
+pub fn apply_alignment(lyrics: &str, aligned_words: &[AlignedWord]) -> (String, Vec<AlignedWord>) {
+  let timecode_regex = Regex::new(TIMECODE_PATTERN).unwrap();
+
+  let lyric_words = get_source_words(lyrics).into_iter()
+    .map(|range| (normalize_word(&get_displayed_text(&lyrics[range.clone()])), range))
+    .filter(|(normalized, _)| !normalized.is_empty())
+    .collect::<Vec<_>>();
+  let aligned_normalized = aligned_words.iter()
+    .map(|word| normalize_word(&word.text))
+    .collect::<Vec<_>>();
+
+  // longest common subsequence of the two word lists
+  let (n, m) = (aligned_normalized.len(), lyric_words.len());
+  let mut lengths = vec![vec![0usize; m + 1]; n + 1];
+  for i in (0..n).rev() {
+    for j in (0..m).rev() {
+      lengths[i][j] = if aligned_normalized[i] == lyric_words[j].0 {
+        lengths[i + 1][j + 1] + 1
+      } else {
+        lengths[i + 1][j].max(lengths[i][j + 1])
+      };
+    }
+  }
+  let mut matches = Vec::new();
+  let mut unmatched_words = Vec::new();
+  let (mut i, mut j) = (0, 0);
+  while i < n {
+    if j < m && aligned_normalized[i] == lyric_words[j].0 {
+      matches.push((i, j));
+      i += 1;
+      j += 1;
+    } else if j < m && lengths[i][j + 1] >= lengths[i + 1][j] {
+      j += 1;
+    } else {
+      unmatched_words.push(aligned_words[i].clone());
+      i += 1;
+    }
+  }
+
+  // the new timestamps are inserted, and the old ones in the blocks they go
+  // in are removed
+  let mut replacements: Vec<(Range<usize>, String)> = Vec::new();
+  for (match_idx, (aligned_idx, lyric_idx)) in matches.iter().enumerate() {
+    let word = &aligned_words[*aligned_idx];
+    let range = &lyric_words[*lyric_idx].1;
+    replacements.push((range.start..range.start, format_timecode(&Duration::from_secs_f32(word.start))));
+
+    let needs_end = match matches.get(match_idx + 1) {
+      Some((next_aligned_idx, next_lyric_idx)) => {
+        aligned_words[*next_aligned_idx].start - word.end >= END_GAP
+          || is_block_break(&lyrics[range.end..lyric_words[*next_lyric_idx].1.start])
+      },
+      None => true,
+    };
+    if needs_end {
+      replacements.push((range.end..range.end, format_syllable_end_timecode(&Duration::from_secs_f32(word.end))));
+    }
+  }
+  for block_range in get_block_ranges(lyrics) {
+    let has_match = matches.iter()
+      .any(|(_, lyric_idx)| block_range.contains(&lyric_words[*lyric_idx].1.start));
+    if has_match {
+      replacements.extend(timecode_regex.find_iter(&lyrics[block_range.clone()])
+        .map(|timecode| ((block_range.start + timecode.start())..(block_range.start + timecode.end()), String::new())));
+    }
+  }
+
+  // replace from the end so earlier positions stay valid
+  replacements.sort_by_key(|(range, _)| (range.start, range.end));
+  let mut lyrics = lyrics.to_string();
+  for (range, timecode) in replacements.into_iter().rev() {
+    lyrics.replace_range(range, &timecode);
+  }
+
+  (lyrics, unmatched_words)
+}
+
+/// Gets whether the source text between two words has an empty line in it.
+fn is_block_break(between: &str) -> bool {
+  let lines = between.split('\n').collect::<Vec<_>>();
+  lines.len() > 2 && lines[1..(lines.len() - 1)].iter().any(|line| line.trim().is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn word(text: &str, start: f32, end: f32) -> AlignedWord {
+    AlignedWord { text: text.into(), start, end }
+  }
+
+  fn summarize(aligned_words: &[AlignedWord]) -> Vec<(&str, f32, f32)> {
+    aligned_words.iter().map(|word| (word.text.as_str(), word.start, word.end)).collect()
+  }
+
+  #[test]
+  fn parses_gentle_json() {
+    let contents = r#"{
+      "transcript": "Hello there world",
+      "words": [
+        {"alignedWord": "hello", "case": "success", "start": 1.0, "end": 1.5, "word": "Hello"},
+        {"case": "not-found-in-audio", "word": "there"},
+        {"alignedWord": "world", "case": "success", "start": 2.0, "end": 2.5, "word": "world"}
+      ]
+    }"#;
+    assert_eq!(summarize(&parse_json(contents).unwrap()), vec![("Hello", 1., 1.5), ("world", 2., 2.5)]);
+  }
+
+  #[test]
+  fn parses_whisperx_json() {
+    let contents = r#"{
+      "segments": [
+        {"start": 0.5, "end": 2.5, "text": " Hello world.", "words": [
+          {"word": "Hello", "start": 0.5, "end": 1.0, "score": 0.9},
+          {"word": "world.", "start": 1.5, "end": 2.5, "score": 0.8}
+        ]}
+      ],
+      "word_segments": [
+        {"word": "Hello", "start": 0.5, "end": 1.0, "score": 0.9},
+        {"word": "world.", "start": 1.5, "end": 2.5, "score": 0.8}
+      ]
+    }"#;
+    assert_eq!(summarize(&parse_json(contents).unwrap()), vec![("Hello", 0.5, 1.), ("world.", 1.5, 2.5)]);
+  }
+
+  #[test]
+  fn parses_mfa_json() {
+    let contents = r#"{
+      "start": 0, "end": 3,
+      "tiers": {
+        "phones": {"type": "interval", "entries": [[0.5, 0.75, "HH"]]},
+        "words": {"type": "interval", "entries": [[0.5, 1.0, "hello"], [1.0, 1.25, ""], [1.25, 2.0, "world"]]}
+      }
+    }"#;
+    assert_eq!(summarize(&parse_json(contents).unwrap()), vec![("hello", 0.5, 1.), ("world", 1.25, 2.)]);
+  }
+
+  #[test]
+  fn parses_text_grid_words_tier() {
+    let contents = r#"File type = "ooTextFile"
+Object class = "TextGrid"
+
+xmin = 0
+xmax = 3
+tiers? <exists>
+size = 2
+item []:
+    item [1]:
+        class = "IntervalTier"
+        name = "phones"
+        xmin = 0
+        xmax = 3
+        intervals: size = 1
+        intervals [1]:
+            xmin = 0.5
+            xmax = 0.75
+            text = "HH"
+    item [2]:
+        class = "IntervalTier"
+        name = "words"
+        xmin = 0
+        xmax = 3
+        intervals: size = 3
+        intervals [1]:
+            xmin = 0
+            xmax = 0.5
+            text = "sil"
+        intervals [2]:
+            xmin = 0.5
+            xmax = 1
+            text = "hello"
+        intervals [3]:
+            xmin = 1.25
+            xmax = 2
+            text = """world"""
+"#;
+    assert_eq!(summarize(&parse_text_grid(contents).unwrap()), vec![("hello", 0.5, 1.), ("\"world\"", 1.25, 2.)]);
+  }
+
+  #[test]
+  fn text_grid_without_tiers_is_an_error() {
+    assert!(parse_text_grid("File type = \"ooTextFile\"").is_err());
+  }
+
+  #[test]
+  fn finishing_drops_silences_and_sorts() {
+    let aligned_words = vec![word("world", 2., 2.5), word("<sp>", 1.5, 2.), word("Hello", 1., 1.5), word("...", 2.5, 3.)];
+    assert_eq!(summarize(&finish_aligned_words(aligned_words).unwrap()), vec![("Hello", 1., 1.5), ("world", 2., 2.5)]);
+    assert!(finish_aligned_words(vec![word("sil", 0., 1.)]).is_err());
+  }
+
+  #[test]
+  fn finds_block_breaks() {
+    assert!(is_block_break("\n\n"));
+    assert!(is_block_break(" \n  \n"));
+    assert!(!is_block_break(" "));
+    assert!(!is_block_break("\n"));
+  }
+
+  #[test]
+  fn times_matched_words() {
+    let aligned_words = [word("hello", 1., 1.5), word("world", 1.5, 2.), word("goodbye", 3., 4.)];
+    let (lyrics, unmatched_words) = apply_alignment("Hello world\n\nGoodbye!", &aligned_words);
+    assert_eq!(lyrics, "[00:01.000]Hello [00:01.500]world[00:02.000>]\n\n[00:03.000]Goodbye![00:04.000>]");
+    assert!(unmatched_words.is_empty());
+  }
+
+  #[test]
+  fn ends_words_before_pauses() {
+    let aligned_words = [word("one", 1., 1.25), word("two", 2., 2.5)];
+    let (lyrics, _) = apply_alignment("one two", &aligned_words);
+    assert_eq!(lyrics, "[00:01.000]one[00:01.250>] [00:02.000]two[00:02.500>]");
+  }
+
+  #[test]
+  fn skips_unmatched_words() {
+    let aligned_words = [word("hello", 1., 1.5), word("um", 1.5, 1.75), word("world", 1.75, 2.)];
+    let (lyrics, unmatched_words) = apply_alignment("Hello big world", &aligned_words);
+    assert_eq!(lyrics, "[00:01.000]Hello[00:01.500>] big [00:01.750]world[00:02.000>]");
+    assert_eq!(summarize(&unmatched_words), vec![("um", 1.5, 1.75)]);
+  }
+
+  #[test]
+  fn replaces_timing_in_matched_blocks() {
+    let aligned_words = [word("hello", 1., 1.5), word("world", 1.5, 2.)];
+    let (lyrics, _) = apply_alignment("[color=red][00:09.000]Hel[00:09.500]lo [00:10.000]world[00:11.000>]", &aligned_words);
+    assert_eq!(lyrics, "[color=red][00:01.000]Hello [00:01.500]world[00:02.000>]");
+  }
+
+  #[test]
+  fn keeps_timing_outside_matched_blocks() {
+    let aligned_words = [word("hello", 5., 5.5), word("world", 5.5, 6.)];
+    let lyrics = "[00:01.000]Old [00:01.500]block[00:02.000>]\n\nHello world\n\n[00:08.000]Later";
+    let (lyrics, _) = apply_alignment(lyrics, &aligned_words);
+    assert_eq!(lyrics,
+      "[00:01.000]Old [00:01.500]block[00:02.000>]\n\n[00:05.000]Hello [00:05.500]world[00:06.000>]\n\n[00:08.000]Later");
+  }
+
+  #[test]
+  fn matches_displayed_text_of_ruby_and_syllables() {
+    let aligned_words = [word("漢字", 1., 1.5), word("syllable", 1.5, 2.)];
+    let (lyrics, _) = apply_alignment("{漢|かん}{字|じ} syl|la|ble", &aligned_words);
+    assert_eq!(lyrics, "[00:01.000]{漢|かん}{字|じ} [00:01.500]syl|la|ble[00:02.000>]");
+  }
+}
@@ -1,12 +1,17 @@
 use bevy::prelude::*;
 use kira::sound::streaming::StreamingSoundData;
+use kira::sound::FromFileError;
 use kira::{
   DefaultBackend,
   AudioManagerSettings,
   Tween
 };
 use kira::AudioManager;
+use kira::Decibels;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 
+use crate::beats::make_click_sound;
 use crate::editor::{show_and_log_error, EditorState};
 
 pub struct AudioPlugin;
@@ -15,6 +20,25 @@ impl Plugin for AudioPlugin {
   fn build(&self, app: &mut App) {
    app.add_systems(Startup, startup);
    app.add_systems(Update, update); 
+   app.add_systems(Update, update_metronome);
+   app.register_type::<GuideVocalSettings>();
+  }
+}
+
+/// How the guide vocal is used in the export.
+#[derive(Serialize, Deserialize, Reflect, Clone)]
+pub struct GuideVocalSettings {
+  pub export_guide_vocal: bool,
+  /// In decibels.
+  pub export_vocal_level: f32,
+}
+
+impl Default for GuideVocalSettings {
+  fn default() -> Self {
+    Self {
+      export_guide_vocal: false,
+      export_vocal_level: -18.,
+    }
   }
 }
 
@@ -34,43 +58,168 @@ fn startup(mut editor_state: NonSendMut<EditorState>,
   }
 }
 
+/// Loads a sound to stream, naming the kind of file in any error.
+fn load_sound(editor_state: &mut EditorState, path: &PathBuf, kind: &str) -> Option<StreamingSoundData<FromFileError>> {
+  match StreamingSoundData::from_file(path.clone()) {
+    Ok(data) => Some(data),
+    Err(e) => {
+      show_and_log_error(editor_state, 
+        format!("Failed to load {} {:?}: {:?}", kind, path, e)
+      );
+      None
+    }
+  }
+}
+
 fn update(mut editor_state: NonSendMut<EditorState>, 
   mut audio_state: NonSendMut<crate::editor::AudioState>
 ) {
   if audio_state.music_handle.is_none() {
-    let mut music = None;
-    if editor_state.project_data.is_some() {
-      if editor_state.project_data.as_ref().unwrap().song_file.is_some() {
-        let song_file = editor_state.project_data.as_ref().unwrap().song_file.as_ref().unwrap().clone();
-          match StreamingSoundData::from_file(song_file.clone()) {
-              Ok(data) => {
-                  music = Some(data);
-              },
-              Err(e) => {
-                  show_and_log_error(editor_state.as_mut(), 
-                    format!("Failed to load music file {:?}: {:?}", 
-                      song_file, e)
-                  );
-              }
-          }
-      }
+    let Some(project_data) = editor_state.project_data.as_ref() else {
+      return;
+    };
+    let song_file = project_data.song_file.clone();
+    let vocal_file = project_data.vocal_file.clone();
+
+    let music = song_file.and_then(|song_file| load_sound(editor_state.as_mut(), &song_file, "music file"));
+    let Some(music) = music else {
+      return;
+    };
+    let vocal = vocal_file.and_then(|vocal_file| load_sound(editor_state.as_mut(), &vocal_file, "guide vocal file"));
+
+    audio_state.duration = Some(music.duration());
+    let play_result = audio_state.audio_manager.as_mut().unwrap().play(music);
+    match play_result {
+        Ok(mut handle) => {
+            info!("sound played successfully");
+            handle.pause(Tween::default());
+            handle.set_loop_region(..);
+            audio_state.music_handle = Some(handle);
+        },
+        Err(e) => {
+          show_and_log_error(editor_state.as_mut(), 
+            format!("Failed to play sound: {:?}", e));
+          return;
+        }
     }
 
-    if let Some(music) = music {
-      audio_state.duration = Some(music.duration());
-      let play_result = audio_state.audio_manager.as_mut().unwrap().play(music);
-      match play_result {
-          Ok(mut handle) => {
-              info!("sound played successfully");
-              handle.pause(Tween::default());
-              handle.set_loop_region(..);
-              audio_state.music_handle = Some(handle);
-          },
-          Err(e) => {
-            show_and_log_error(editor_state.as_mut(), 
-              format!("Failed to play sound: {:?}", e));
-          }
+    audio_state.vocal_handle = None;
+    if let Some(vocal) = vocal {
+      match audio_state.audio_manager.as_mut().unwrap().play(vocal) {
+        Ok(mut handle) => {
+          handle.pause(Tween::default());
+          handle.set_loop_region(..);
+          audio_state.vocal_handle = Some(handle);
+        },
+        Err(e) => {
+          show_and_log_error(editor_state.as_mut(), 
+            format!("Failed to play guide vocal: {:?}", e));
+        }
       }
     }
+    audio_state.apply_volumes();
+  }
+}
+
+// sample rate of the generated metronome clicks
+const METRONOME_SAMPLE_RATE: u32 = 44100;
+
+/// Clicks on every beat of the beat grid while playing, if the metronome is on.
+fn update_metronome(mut editor_state: NonSendMut<EditorState>, 
+  mut audio_state: NonSendMut<crate::editor::AudioState>
+) {
+  let beat_grid = editor_state.project_data.as_ref()
+    .and_then(|project_data| project_data.beat_grid.clone());
+  let is_playing = !editor_state.is_paused && !editor_state.is_in_pre_delay;
+  let Some(beat_grid) = beat_grid.filter(|_| audio_state.metronome_enabled && is_playing) else {
+    audio_state.last_metronome_beat = None;
+    return;
+  };
+
+  let beat = beat_grid.get_beat_at(audio_state.playhead_position().as_secs_f32()).floor() as i64;
+  let prev_beat = audio_state.last_metronome_beat.replace(beat);
+  // only click when a beat starts, not partway through one after starting 
+  // playback
+  if prev_beat.is_none() || prev_beat == Some(beat) || beat < 0 {
+    return;
+  }
+
+  let volume = audio_state.volume;
+  let click = make_click_sound(METRONOME_SAMPLE_RATE, beat_grid.is_downbeat(beat)).volume(volume);
+  if let Some(audio_manager) = audio_state.audio_manager.as_mut() {
+    if let Err(e) = audio_manager.play(click) {
+      show_and_log_error(editor_state.as_mut(), format!("Failed to play metronome click: {:?}", e));
+    }
+  }
+}
+
+/// Volume for a track relative to the main volume. -60dB and below is silent.
+pub fn mix_volume(volume: Decibels, level: Decibels) -> Decibels {
+  if level <= Decibels::SILENCE {
+    Decibels::SILENCE
+  } else {
+    Decibels(volume.0 + level.0)
+  }
+}
+
+/// Second-order IIR filter, with coefficients from the Audio EQ Cookbook.
+pub struct Biquad {
+  b0: f32,
+  b1: f32,
+  b2: f32,
+  a1: f32,
+  a2: f32,
+  x1: f32,
+  x2: f32,
+  y1: f32,
+  y2: f32,
+}
+
+impl Biquad {
+  /// Butterworth low-pass filter.
+  pub fn low_pass(cutoff: f32, sample_rate: f32) -> Self {
+    let (cos_omega, alpha) = Self::omega(cutoff, std::f32::consts::FRAC_1_SQRT_2, sample_rate);
+    Self::new(
+      (1. - cos_omega) / 2., 1. - cos_omega, (1. - cos_omega) / 2.,
+      1. + alpha, -2. * cos_omega, 1. - alpha
+    )
   }
-}
\ No newline at end of file
+
+  /// Band-pass filter with 0dB gain at `center`.
+  pub fn band_pass(center: f32, q: f32, sample_rate: f32) -> Self {
+    let (cos_omega, alpha) = Self::omega(center, q, sample_rate);
+    Self::new(
+      alpha, 0., -alpha,
+      1. + alpha, -2. * cos_omega, 1. - alpha
+    )
+  }
+
+  fn omega(frequency: f32, q: f32, sample_rate: f32) -> (f32, f32) {
+    let frequency = frequency.clamp(1., sample_rate / 2. - 1.);
+    let omega = 2. * std::f32::consts::PI * frequency / sample_rate;
+    (omega.cos(), omega.sin() / (2. * q))
+  }
+
+  fn new(b0: f32, b1: f32, b2: f32, a0: f32, a1: f32, a2: f32) -> Self {
+    Self {
+      b0: b0 / a0,
+      b1: b1 / a0,
+      b2: b2 / a0,
+      a1: a1 / a0,
+      a2: a2 / a0,
+      x1: 0.,
+      x2: 0.,
+      y1: 0.,
+      y2: 0.,
+    }
+  }
+
+  pub fn process(&mut self, x: f32) -> f32 {
+    let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2 - self.a1 * self.y1 - self.a2 * self.y2;
+    self.x2 = self.x1;
+    self.x1 = x;
+    self.y2 = self.y1;
+    self.y1 = y;
+    y
+  }
+}
@@ -0,0 +1,465 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::fs;
+use std::process::Stdio;
+
+use bevy::prelude::*;
+use bevy::asset::RenderAssetUsages;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy::render::view::RenderLayers;
+use bevy_egui::{egui, EguiContexts};
+use bevy_file_dialog::prelude::*;
+use bevy_tokio_tasks::TokioTasksRuntime;
+use directories::ProjectDirs;
+use ffmpeg_cli::{FfmpegBuilder, File, Parameter};
+use image::ImageReader;
+use serde::{Serialize, Deserialize};
+
+use crate::editor::{show_and_log_error, show_and_log_info, AudioState, EditorState};
+use crate::export::ExportState;
+
+pub struct BackgroundPlugin;
+
+impl Plugin for BackgroundPlugin {
+  fn build(&self, app: &mut App) {
+    app.add_systems(Startup, startup);
+    app.add_systems(Update, start_video_decode);
+    app.add_systems(Update, update_background);
+    app.add_systems(Update, handle_background_video_dialog);
+    app.add_systems(Update, handle_background_slide_dialog);
+    app.insert_resource(BackgroundState::default());
+    app.insert_resource(BackgroundDialog::default());
+  }
+}
+
+// backgrounds are decoded and shown at the same frame rate as export
+const BACKGROUND_FPS: f64 = 12.;
+// written to a frames dir once ffmpeg has decoded the whole video into it
+const FRAMES_DONE_FILE: &str = "done";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BackgroundSettings {
+  /// Takes priority over the slides.
+  pub video_path: Option<PathBuf>,
+  pub slides: Vec<BackgroundSlide>,
+  /// In seconds.
+  pub crossfade_time: f32,
+}
+
+impl Default for BackgroundSettings {
+  fn default() -> Self {
+    Self {
+      video_path: None,
+      slides: Vec::new(),
+      crossfade_time: 1.,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BackgroundSlide {
+  pub image_path: PathBuf,
+  /// Seconds from the start of the video, including the pre-delay.
+  pub start_time: f32,
+}
+
+#[derive(Resource)]
+struct BackgroundState {
+  video_frame_image: Handle<Image>,
+  /// The video whose frames are currently in `video_frames_dir`.
+  decoded_video: Option<PathBuf>,
+  video_frames_dir: Option<PathBuf>,
+  video_frames_ready: bool,
+  curr_video_frame: Option<usize>,
+  slide_images: HashMap<PathBuf, Handle<Image>>,
+  /// Images being decoded on a background task.
+  loading_images: HashSet<AssetId<Image>>,
+}
+
+impl Default for BackgroundState {
+  fn default() -> Self {
+    Self {
+      video_frame_image: Handle::default(),
+      decoded_video: None,
+      video_frames_dir: None,
+      video_frames_ready: false,
+      curr_video_frame: None,
+      slide_images: HashMap::new(),
+      loading_images: HashSet::new(),
+    }
+  }
+}
+
+/// Layer 1 is drawn over layer 0 while crossfading.
+#[derive(Component)]
+struct BackgroundSprite {
+  layer: usize,
+}
+
+fn startup(mut commands: Commands, mut background_state: ResMut<BackgroundState>,
+  mut images: ResMut<Assets<Image>>
+) {
+  background_state.video_frame_image = images.add(Image::default());
+
+  for layer in 0..2 {
+    commands.spawn((
+      Sprite {
+        color: Color::NONE,
+        custom_size: Some(Vec2::new(1920., 1080.)),
+        ..default()
+      },
+      Transform::from_translation(Vec3::new(0., 0., -10. + layer as f32)),
+      RenderLayers::layer(1),
+      BackgroundSprite { layer }
+    ));
+  }
+}
+
+/// Each video gets its own frames dir, named from its path, size and modified time.
+fn get_frames_dir(video_path: &Path) -> PathBuf {
+  let mut hasher = DefaultHasher::new();
+  fs::canonicalize(video_path).unwrap_or_else(|_| video_path.into()).hash(&mut hasher);
+  if let Ok(metadata) = fs::metadata(video_path) {
+    metadata.len().hash(&mut hasher);
+    metadata.modified().ok().hash(&mut hasher);
+  }
+  ProjectDirs::from("", "yoteoke", "yoteoke").unwrap().cache_dir()
+    .join(Path::new("background"))
+    .join(format!("{:016x}", hasher.finish()))
+}
+
+/// Decodes the background video into frames when it changes, unless that's already done.
+fn start_video_decode(mut background_state: ResMut<BackgroundState>,
+  editor_state: NonSend<EditorState>,
+  tokio_runtime: Res<TokioTasksRuntime>
+) {
+  let video_path = editor_state.project_data.as_ref()
+    .and_then(|project_data| project_data.background.as_ref())
+    .and_then(|background| background.video_path.clone());
+
+  if video_path == background_state.decoded_video {
+    return;
+  }
+
+  background_state.decoded_video = video_path.clone();
+  background_state.video_frames_dir = None;
+  background_state.video_frames_ready = false;
+  background_state.curr_video_frame = None;
+
+  let Some(video_path) = video_path else {
+    return;
+  };
+
+  let frames_dir = get_frames_dir(&video_path);
+  background_state.video_frames_dir = Some(frames_dir.clone());
+  if frames_dir.join(FRAMES_DONE_FILE).exists() {
+    background_state.video_frames_ready = true;
+    return;
+  }
+  // left over from a decode that didn't finish
+  let _ = fs::remove_dir_all(&frames_dir);
+  if let Err(e) = fs::create_dir_all(&frames_dir) {
+    error!("Couldn't create background frames dir {:?}: {:?}", frames_dir, e);
+    return;
+  }
+
+  tokio_runtime.spawn_background_task(|mut ctx| async move {
+    let input_path: String = video_path.as_os_str().to_string_lossy().into();
+    let output_path: String = frames_dir.join("%05d.jpg").as_os_str().to_string_lossy().into();
+    let filter = format!("fps={},scale=1920:1080:force_original_aspect_ratio=decrease,pad=1920:1080:(ow-iw)/2:(oh-ih)/2",
+      BACKGROUND_FPS);
+
+    let builder = FfmpegBuilder::new()
+      .stderr(Stdio::piped())
+      .option(Parameter::Single("nostdin"))
+      .option(Parameter::Single("y"))
+      .input(File::new(&input_path))
+      .output(
+        File::new(&output_path)
+          .option(Parameter::KeyValue("vf", &filter))
+          .option(Parameter::KeyValue("q:v", "3"))
+      );
+
+    let result = match builder.run().await {
+      Ok(ffmpeg) => match ffmpeg.process.wait_with_output() {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(output) => Err(String::from_utf8_lossy(&output.stderr).into_owned()),
+        Err(e) => Err(format!("{:?}", e)),
+      },
+      Err(e) => Err(format!("{:?}", e)),
+    }.and_then(|_| fs::write(frames_dir.join(FRAMES_DONE_FILE), "").map_err(|e| format!("{:?}", e)));
+
+    ctx.run_on_main_thread(move |ctx| {
+      {
+        let mut background_state = ctx.world.resource_mut::<BackgroundState>();
+        // the video may have been changed again while this one was decoding
+        if background_state.decoded_video.as_ref() != Some(&video_path) {
+          return;
+        }
+        background_state.video_frames_ready = result.is_ok();
+      }
+
+      let mut editor_state = ctx.world.get_non_send_resource_mut::<EditorState>().unwrap();
+      match result {
+        Ok(_) => show_and_log_info(editor_state.as_mut(),
+          format!("Background video {:?} decoded", video_path)),
+        Err(e) => show_and_log_error(editor_state.as_mut(),
+          format!("Error decoding background video {:?}: {}", video_path, e)),
+      }
+    }).await;
+  });
+}
+
+fn load_image(path: &Path) -> Result<Image, String> {
+  let decoded = ImageReader::open(path)
+    .map_err(|e| format!("{:?}", e))?
+    .decode()
+    .map_err(|e| format!("{:?}", e))?;
+
+  Ok(Image::new(
+    Extent3d {
+      width: decoded.width(),
+      height: decoded.height(),
+      depth_or_array_layers: 1
+    },
+    TextureDimension::D2,
+    decoded.to_rgba8().into_vec(),
+    TextureFormat::Rgba8UnormSrgb,
+    RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD))
+}
+
+/// Loads an image into `handle`, right away while exporting and on a background task otherwise.
+fn load_image_into(path: PathBuf, handle: &Handle<Image>, is_exporting: bool,
+  background_state: &mut BackgroundState, images: &mut Assets<Image>, tokio_runtime: &TokioTasksRuntime
+) {
+  if is_exporting {
+    match load_image(&path) {
+      Ok(image) => {
+        images.insert(handle, image);
+      },
+      Err(e) => {
+        warn!("Couldn't load background image {:?}: {}", path, e);
+      }
+    }
+    return;
+  }
+
+  background_state.loading_images.insert(handle.id());
+  let handle = handle.clone();
+  tokio_runtime.spawn_background_task(|mut ctx| async move {
+    let result = load_image(&path);
+
+    ctx.run_on_main_thread(move |ctx| {
+      match result {
+        Ok(image) => {
+          ctx.world.resource_mut::<Assets<Image>>().insert(&handle, image);
+        },
+        Err(e) => {
+          warn!("Couldn't load background image {:?}: {}", path, e);
+        }
+      }
+      ctx.world.resource_mut::<BackgroundState>().loading_images.remove(&handle.id());
+    }).await;
+  });
+}
+
+fn update_background(mut background_state: ResMut<BackgroundState>,
+  editor_state: NonSend<EditorState>,
+  audio_state: NonSend<AudioState>,
+  export_state: Res<ExportState>,
+  mut images: ResMut<Assets<Image>>,
+  mut sprite_query: Query<(&BackgroundSprite, &mut Sprite)>,
+  tokio_runtime: Res<TokioTasksRuntime>
+) {
+  let background_state = background_state.as_mut();
+  // what to show on each background sprite, and how opaque
+  let mut layers: [Option<(Handle<Image>, f32)>; 2] = [None, None];
+
+  if let Some(background) = editor_state.project_data.as_ref().and_then(|data| data.background.as_ref()) {
+    // the background runs on the same clock as the timeline, pre-delay included
+    let stage_time = if export_state.is_exporting() {
+      export_state.frame_idx() as f64 / BACKGROUND_FPS
+    } else {
+      audio_state.playhead_position().as_secs_f64() + editor_state.curr_pre_delay_time
+    };
+
+    if background.video_path.is_some() {
+      if let (true, Some(frames_dir)) = (background_state.video_frames_ready, background_state.video_frames_dir.clone()) {
+        // ffmpeg numbers frames from 1
+        let frame_idx = (stage_time * BACKGROUND_FPS) as usize + 1;
+        let video_frame_image = background_state.video_frame_image.clone();
+        // while a frame is loading, the next one waits for it so they don't 
+        // pile up
+        if background_state.curr_video_frame != Some(frame_idx) && (export_state.is_exporting()
+          || !background_state.loading_images.contains(&video_frame_image.id()))
+        {
+          let frame_path = frames_dir.join(format!("{:05}.jpg", frame_idx));
+          // past the end of the video, the last frame stays up
+          if frame_path.exists() {
+            load_image_into(frame_path, &video_frame_image, export_state.is_exporting(), background_state,
+              &mut images, &tokio_runtime);
+          }
+          background_state.curr_video_frame = Some(frame_idx);
+        }
+        layers[0] = Some((video_frame_image, 1.));
+      }
+    } else {
+      let stage_time = stage_time as f32;
+      let mut slides = background.slides.clone();
+      slides.sort_by(|a, b| a.start_time.total_cmp(&b.start_time));
+
+      let curr_idx = slides.iter().rposition(|slide| slide.start_time <= stage_time);
+      let next_idx = curr_idx.map_or(0, |idx| idx + 1);
+      for (layer, slide_idx) in [curr_idx, Some(next_idx)].into_iter().enumerate() {
+        let Some(slide) = slide_idx.and_then(|idx| slides.get(idx)) else {
+          continue;
+        };
+
+        let alpha = if layer == 0 {
+          1.
+        } else if background.crossfade_time > 0. {
+          (1. - (slide.start_time - stage_time) / background.crossfade_time).clamp(0., 1.)
+        } else {
+          0.
+        };
+        if alpha <= 0. {
+          continue;
+        }
+
+        if !background_state.slide_images.contains_key(&slide.image_path) {
+          let handle = images.reserve_handle();
+          background_state.slide_images.insert(slide.image_path.clone(), handle.clone());
+          load_image_into(slide.image_path.clone(), &handle, export_state.is_exporting(), background_state,
+            &mut images, &tokio_runtime);
+        }
+        layers[layer] = Some((background_state.slide_images[&slide.image_path].clone(), alpha));
+      }
+    }
+  }
+
+  for (background_sprite, mut sprite) in sprite_query.iter_mut() {
+    if let Some((image, alpha)) = &layers[background_sprite.layer] {
+      sprite.image = image.clone();
+      sprite.color = Color::srgba(1., 1., 1., *alpha);
+    } else {
+      sprite.image = Handle::default();
+      sprite.color = Color::NONE;
+    }
+  }
+}
+
+#[derive(Default, Resource)]
+pub struct BackgroundDialog {
+  is_open: bool,
+}
+
+impl BackgroundDialog {
+  pub fn open(&mut self) {
+    self.is_open = true;
+  }
+}
+
+pub struct BackgroundVideoDialog;
+
+pub struct BackgroundSlideDialog;
+
+pub fn configure_file_dialog_plugin(plugin: FileDialogPlugin) -> FileDialogPlugin {
+  plugin.with_pick_file::<BackgroundVideoDialog>()
+    .with_pick_file::<BackgroundSlideDialog>()
+}
+
+pub fn background_dialog_ui(mut egui_contexts: EguiContexts,
+  mut background_dialog: ResMut<BackgroundDialog>,
+  mut editor_state: NonSendMut<EditorState>,
+  mut commands: Commands
+) {
+  if !background_dialog.is_open {
+    return;
+  }
+  let Some(project_data) = editor_state.project_data.as_mut() else {
+    return;
+  };
+
+  let mut changed = false;
+  let mut is_open = true;
+  let background = project_data.background.get_or_insert_with(BackgroundSettings::default);
+  egui::Window::new("Stage Background").open(&mut is_open).show(egui_contexts.ctx_mut(), |ui| {
+    ui.horizontal(|ui| {
+      ui.label("Video");
+      if let Some(video_path) = &background.video_path {
+        ui.label(video_path.as_os_str().to_string_lossy());
+        if ui.button("Clear").clicked() {
+          background.video_path = None;
+          changed = true;
+        }
+      } else {
+        ui.label("None");
+      }
+      if ui.button("Browse...").clicked() {
+        commands.dialog().add_filter("Video file", &["mp4", "mov", "mkv", "webm", "avi"]).pick_file_path::<BackgroundVideoDialog>();
+      }
+    });
+
+    ui.separator();
+    ui.label("Slides (used when there's no video)");
+    let mut slide_to_remove = None;
+    egui::Grid::new("background_slides").striped(true).show(ui, |ui| {
+      for (idx, slide) in background.slides.iter_mut().enumerate() {
+        ui.label(slide.image_path.file_name().unwrap_or_default().to_string_lossy());
+        changed |= ui.add(egui::DragValue::new(&mut slide.start_time).speed(0.1).range(0. ..=f32::MAX).suffix("s")).changed();
+        if ui.button("Remove").clicked() {
+          slide_to_remove = Some(idx);
+        }
+        ui.end_row();
+      }
+    });
+    if let Some(idx) = slide_to_remove {
+      background.slides.remove(idx);
+      changed = true;
+    }
+    if ui.button("Add Slide...").clicked() {
+      commands.dialog().add_filter("Image file", &["png", "jpeg", "jpg", "bmp", "tga", "tiff", "webp"]).pick_file_path::<BackgroundSlideDialog>();
+    }
+
+    ui.horizontal(|ui| {
+      ui.label("Crossfade Time");
+      changed |= ui.add(egui::DragValue::new(&mut background.crossfade_time).speed(0.05).range(0. ..=f32::MAX).suffix("s")).changed();
+    });
+  });
+
+  background_dialog.is_open = is_open;
+  if changed {
+    editor_state.needs_save_before_exit = true;
+  }
+}
+
+fn handle_background_video_dialog(mut events: EventReader<DialogFilePicked<BackgroundVideoDialog>>,
+  mut editor_state: NonSendMut<EditorState>
+) {
+  for ev in events.read() {
+    if let Some(project_data) = editor_state.project_data.as_mut() {
+      project_data.background.get_or_insert_with(BackgroundSettings::default).video_path = Some(ev.path.clone());
+      editor_state.needs_save_before_exit = true;
+    }
+  }
+}
+
+fn handle_background_slide_dialog(mut events: EventReader<DialogFilePicked<BackgroundSlideDialog>>,
+  mut editor_state: NonSendMut<EditorState>
+) {
+  for ev in events.read() {
+    if let Some(project_data) = editor_state.project_data.as_mut() {
+      let background = project_data.background.get_or_insert_with(BackgroundSettings::default);
+      // new slides go five seconds after the last one
+      let start_time = background.slides.iter()
+        .map(|slide| slide.start_time + 5.)
+        .fold(0., f32::max);
+      background.slides.push(BackgroundSlide {
+        image_path: ev.path.clone(),
+        start_time
+      });
+      editor_state.needs_save_before_exit = true;
+    }
+  }
+}
@@ -0,0 +1,290 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_tokio_tasks::TokioTasksRuntime;
+use kira::sound::static_sound::{StaticSoundData, StaticSoundSettings};
+use kira::Frame;
+use serde::{Deserialize, Serialize};
+
+use crate::editor::{show_and_log_error, show_and_log_info, EditorState};
+use crate::lyrics::retime_timecodes;
+use crate::onsets::get_onset_envelope;
+
+pub struct BeatsPlugin;
+
+impl Plugin for BeatsPlugin {
+  fn build(&self, app: &mut App) {
+    app.add_event::<DetectBeatGridRequestedEvent>();
+    app.add_systems(Update, handle_detect_beat_grid_requested);
+    app.insert_resource(BeatDetectionState::default());
+    app.register_type::<BeatGrid>();
+  }
+}
+
+// tempo range considered when estimating, in beats per minute
+const MIN_BPM: f32 = 60.;
+const MAX_BPM: f32 = 200.;
+// estimates are nudged towards this tempo, to avoid picking half or double time
+const PREFERRED_BPM: f32 = 120.;
+
+/// The song's tempo and where its beats fall.
+#[derive(Serialize, Deserialize, Reflect, Clone)]
+pub struct BeatGrid {
+  pub bpm: f32,
+  /// Song time of a downbeat, in seconds.
+  pub offset: f32,
+  pub beats_per_bar: u32,
+  /// Steps per beat when quantizing, e.g. 2 for eighth notes.
+  pub quantize_division: u32,
+  pub show_grid: bool,
+}
+
+impl Default for BeatGrid {
+  fn default() -> Self {
+    Self {
+      bpm: 120.,
+      offset: 0.,
+      beats_per_bar: 4,
+      quantize_division: 2,
+      show_grid: false,
+    }
+  }
+}
+
+impl BeatGrid {
+  pub fn beat_length(&self) -> f32 {
+    60. / self.bpm.max(1.)
+  }
+
+  /// Gets which beat falls at the given song time. Beat 0 is at the offset.
+  pub fn get_beat_at(&self, time: f32) -> f32 {
+    (time - self.offset) / self.beat_length()
+  }
+
+  pub fn get_beat_time(&self, beat: f32) -> f32 {
+    self.offset + beat * self.beat_length()
+  }
+
+  pub fn is_downbeat(&self, beat: i64) -> bool {
+    beat.rem_euclid(self.beats_per_bar.max(1) as i64) == 0
+  }
+
+  /// Rounds a song time to the nearest step of the grid.
+  pub fn quantize(&self, time: f32) -> f32 {
+    let divisions = self.quantize_division.max(1) as f32;
+    let step = (self.get_beat_at(time) * divisions).round() / divisions;
+    self.get_beat_time(step).max(0.)
+  }
+}
+
+/// Snaps every timestamp to the beat grid. Returns the new lyrics and how many moved.
+pub fn quantize_timestamps(lyrics: &str, beat_grid: &BeatGrid) -> (String, usize) {
+  retime_timecodes(lyrics, |time| {
+    Some(Duration::from_secs_f32(beat_grid.quantize(time.as_secs_f32())))
+  })
+}
+
+#[derive(Event, Default)]
+pub struct DetectBeatGridRequestedEvent;
+
+#[derive(Resource, Default)]
+pub struct BeatDetectionState {
+  is_detecting: bool,
+}
+
+impl BeatDetectionState {
+  pub fn is_detecting(&self) -> bool {
+    self.is_detecting
+  }
+}
+
+fn handle_detect_beat_grid_requested(
+  mut events: EventReader<DetectBeatGridRequestedEvent>,
+  mut editor_state: NonSendMut<EditorState>,
+  mut beat_detection_state: ResMut<BeatDetectionState>,
+  tokio_runtime: Res<TokioTasksRuntime>
+) {
+  for _ in events.read() {
+    if beat_detection_state.is_detecting {
+      continue;
+    }
+
+    let Some(song_file) = editor_state.project_data.as_ref()
+      .and_then(|project_data| project_data.song_file.clone()) else
+    {
+      show_and_log_error(editor_state.as_mut(), "Can't detect the tempo without a song file".into());
+      continue;
+    };
+
+    beat_detection_state.is_detecting = true;
+
+    tokio_runtime.spawn_background_task(|mut ctx| async move {
+      let result = StaticSoundData::from_file(&song_file)
+        .map_err(|e| format!("{:?}", e))
+        .and_then(|sound_data| estimate_beat_grid(&sound_data));
+
+      ctx.run_on_main_thread(move |ctx| {
+        ctx.world.resource_mut::<BeatDetectionState>().is_detecting = false;
+
+        let mut editor_state = ctx.world.get_non_send_resource_mut::<EditorState>().unwrap();
+        match result {
+          Ok((bpm, offset)) => {
+            if let Some(project_data) = editor_state.project_data.as_mut() {
+              let beat_grid = project_data.beat_grid.get_or_insert_with(BeatGrid::default);
+              beat_grid.bpm = bpm;
+              beat_grid.offset = offset;
+              beat_grid.show_grid = true;
+            }
+            editor_state.needs_save_before_exit = true;
+            show_and_log_info(editor_state.as_mut(),
+              format!("Detected {:.1} BPM, first beat at {:.3}s", bpm, offset));
+          },
+          Err(e) => show_and_log_error(editor_state.as_mut(),
+            format!("Error detecting tempo of {:?}: {}", song_file, e)),
+        }
+      }).await;
+    });
+  }
+}
+
+/// Estimates the BPM and first beat time from the onset envelope.
+fn estimate_beat_grid(sound_data: &StaticSoundData) -> Result<(f32, f32), String> {
+  // a wide band, so drums and other rhythm parts count
+  let envelope = get_onset_envelope(sound_data, 2000., 0.3);
+  let flux = &envelope.flux;
+
+  let min_lag = (60. / MAX_BPM / envelope.hop_time).floor() as usize;
+  let max_lag = (60. / MIN_BPM / envelope.hop_time).ceil() as usize;
+  if flux.len() <= max_lag * 2 {
+    return Err("The song is too short to detect a tempo".into());
+  }
+
+  let autocorrelation = |lag: usize| -> f32 {
+    flux.iter().zip(&flux[lag..]).map(|(a, b)| a * b).sum::<f32>() / (flux.len() - lag) as f32
+  };
+  let scores = (min_lag..=max_lag)
+    .map(|lag| {
+      let bpm = 60. / (lag as f32 * envelope.hop_time);
+      let prior = (-(bpm / PREFERRED_BPM).log2().powi(2) * 2.).exp();
+      (lag, autocorrelation(lag) * prior)
+    })
+    .collect::<Vec<_>>();
+  let best_idx = scores.iter()
+    .enumerate()
+    .max_by(|(_, a), (_, b)| a.1.total_cmp(&b.1))
+    .map(|(idx, _)| idx)
+    .unwrap();
+
+  // refine between frames by fitting a parabola through the peak
+  let mut lag = scores[best_idx].0 as f32;
+  if best_idx > 0 && best_idx + 1 < scores.len() {
+    let (before, peak, after) = (scores[best_idx - 1].1, scores[best_idx].1, scores[best_idx + 1].1);
+    let denominator = before - 2. * peak + after;
+    if denominator.abs() > f32::EPSILON {
+      lag += (0.5 * (before - after) / denominator).clamp(-0.5, 0.5);
+    }
+  }
+  let bpm = 60. / (lag * envelope.hop_time);
+
+  // pick the phase the onsets line up with best
+  let phase_steps = lag.ceil() as usize;
+  let best_phase = (0..phase_steps)
+    .map(|phase| {
+      let score = (0..)
+        .map(|beat| (phase as f32 + beat as f32 * lag).round() as usize)
+        .take_while(|idx| *idx < flux.len())
+        .map(|idx| flux[idx])
+        .sum::<f32>();
+      (phase, score)
+    })
+    .max_by(|a, b| a.1.total_cmp(&b.1))
+    .map(|(phase, _)| phase)
+    .unwrap_or(0);
+
+  Ok((bpm, best_phase as f32 * envelope.hop_time))
+}
+
+/// Makes a short click for the metronome, higher pitched for downbeats.
+pub fn make_click_sound(sample_rate: u32, is_downbeat: bool) -> StaticSoundData {
+  let frequency = if is_downbeat { 1600. } else { 1000. };
+  let length = (sample_rate as f32 * 0.03) as usize;
+  let frames = (0..length)
+    .map(|idx| {
+      let t = idx as f32 / sample_rate as f32;
+      let sample = (t * frequency * std::f32::consts::TAU).sin() * (-t * 150.).exp() * 0.5;
+      Frame { left: sample, right: sample }
+    })
+    .collect::<Arc<[Frame]>>();
+
+  StaticSoundData {
+    sample_rate,
+    frames,
+    settings: StaticSoundSettings::default(),
+    slice: None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn beat_grid(bpm: f32, offset: f32, quantize_division: u32) -> BeatGrid {
+    BeatGrid {
+      bpm,
+      offset,
+      quantize_division,
+      ..default()
+    }
+  }
+
+  #[test]
+  fn quantizes_to_nearest_step() {
+    // steps every quarter second from 0.25
+    let beat_grid = beat_grid(120., 0.25, 2);
+    assert_eq!(beat_grid.quantize(1.3), 1.25);
+    // halfway between two steps rounds up
+    assert_eq!(beat_grid.quantize(1.375), 1.5);
+    assert_eq!(beat_grid.quantize(1.374), 1.25);
+    // steps before the offset can't go below zero
+    assert_eq!(beat_grid.quantize(0.1), 0.);
+  }
+
+  #[test]
+  fn quantizes_timestamps() {
+    let (lyrics, moved_count) = quantize_timestamps("[00:01.300]a [00:01.375]b [00:01.750>]", &beat_grid(120., 0.25, 2));
+    assert_eq!(lyrics, "[00:01.250]a [00:01.500]b [00:01.750>]");
+    assert_eq!(moved_count, 2);
+  }
+
+  #[test]
+  fn estimates_tempo_of_click_train() {
+    const SAMPLE_RATE: u32 = 44100;
+    let (bpm, offset) = (100., 0.3);
+    let click = make_click_sound(SAMPLE_RATE, false);
+    let mut frames = vec![Frame::default(); SAMPLE_RATE as usize * 20];
+    for beat in 0.. {
+      let start = ((offset + beat as f32 * 60. / bpm) * SAMPLE_RATE as f32) as usize;
+      let Some(beat_frames) = frames.get_mut(start..(start + click.frames.len())) else {
+        break;
+      };
+      beat_frames.copy_from_slice(&click.frames);
+    }
+    let sound_data = StaticSoundData {
+      sample_rate: SAMPLE_RATE,
+      frames: frames.into(),
+      settings: StaticSoundSettings::default(),
+      slice: None,
+    };
+
+    let (estimated_bpm, estimated_offset) = estimate_beat_grid(&sound_data).unwrap();
+    assert!((estimated_bpm - bpm).abs() < 1., "estimated {} BPM", estimated_bpm);
+    assert!((estimated_offset - offset).abs() < 0.03, "estimated first beat at {}", estimated_offset);
+  }
+
+  #[test]
+  fn short_song_has_no_tempo() {
+    let sound_data = make_click_sound(44100, true);
+    assert!(estimate_beat_grid(&sound_data).is_err());
+  }
+}
@@ -13,6 +13,7 @@ use bevy::ecs::system::SystemState;
 use bevy_egui::EguiContexts;
 use bevy::window::WindowCloseRequested;
 use kira::Decibels;
+use kira::Tween;
 use bevy::winit::WinitWindows;
 use winit::window::Icon;
 use egui_toast::Toasts;
@@ -37,6 +38,7 @@ impl Plugin for EditorPlugin {
     app.add_systems(Update, exit_confirm_dialog_ui);
     app.add_systems(Update, finish_save_and_exit);
     app.insert_resource(TitlecardState::default());
+    app.insert_resource(FontState::default());
     app.add_systems(Startup, set_window_icon);
     app.add_systems(Startup, setup_toasts);
   }
@@ -49,6 +51,8 @@ pub struct EditorState {
   pub new_file_dialog: Option<NewProjectDialog>,
   pub parsed_lyrics: Option<ParsedLyrics>,
   pub lyrics_dirty: bool,
+  /// Bumped every time the lyrics are parsed.
+  pub lyrics_generation: u64,
   pub needs_save_before_exit: bool,
   pub is_in_pre_delay: bool,
   pub curr_pre_delay_time: f64,
@@ -59,9 +63,16 @@ pub struct EditorState {
 #[derive(Default)]
 pub struct AudioState {
   pub music_handle: Option<StreamingSoundHandle<FromFileError>>,
+  pub vocal_handle: Option<StreamingSoundHandle<FromFileError>>,
   pub audio_manager: Option<AudioManager>,
   pub duration: Option<Duration>,
   pub volume: Decibels,
+  /// Level of the guide vocal relative to `volume`.
+  pub vocal_level: Decibels,
+  pub mute_instrumental: bool,
+  pub metronome_enabled: bool,
+  /// The beat the metronome last saw, so it clicks once per beat.
+  pub last_metronome_beat: Option<i64>,
 }
 
 #[derive(Default, Resource)]
@@ -70,6 +81,11 @@ pub struct TitlecardState {
   pub titlecard_egui_tex_id: Option<egui::TextureId>,
 }
 
+#[derive(Default, Resource)]
+pub struct FontState {
+  pub lyric_font: Option<Handle<Font>>,
+}
+
 impl AudioState {
     pub fn playhead_position(&self) -> Duration {
       if let Some(music_handle) = &self.music_handle {
@@ -78,6 +94,41 @@ impl AudioState {
         return Duration::default();
       }
     }
+
+    pub fn pause(&mut self) {
+      for handle in self.music_handle.iter_mut().chain(self.vocal_handle.iter_mut()) {
+        handle.pause(Tween::default());
+      }
+    }
+
+    pub fn resume(&mut self) {
+      for handle in self.music_handle.iter_mut().chain(self.vocal_handle.iter_mut()) {
+        handle.resume(Tween::default());
+      }
+    }
+
+    pub fn seek_to(&mut self, position: f64) {
+      for handle in self.music_handle.iter_mut().chain(self.vocal_handle.iter_mut()) {
+        handle.seek_to(position);
+      }
+    }
+
+    pub fn unload(&mut self) {
+      self.pause();
+      self.music_handle = None;
+      self.vocal_handle = None;
+    }
+
+    pub fn apply_volumes(&mut self) {
+      let music_volume = if self.mute_instrumental { Decibels::SILENCE } else { self.volume };
+      let vocal_volume = crate::audio::mix_volume(self.volume, self.vocal_level);
+      if let Some(music_handle) = &mut self.music_handle {
+        music_handle.set_volume(music_volume, Tween::default());
+      }
+      if let Some(vocal_handle) = &mut self.vocal_handle {
+        vocal_handle.set_volume(vocal_volume, Tween::default());
+      }
+    }
 }
 
 fn ui(world: &mut World) {
@@ -103,7 +154,8 @@ fn ui(world: &mut World) {
       );
       egui::CentralPanel::default().show_inside(ui, |ui| {
         egui::TopBottomPanel::new(egui::panel::TopBottomSide::Bottom, "timeline_panel")
-          .exact_height(256.)
+          .resizable(true)
+          .default_height(256.)
           .show_inside(ui, |ui|
           {
             world.run_system_cached_with(crate::timeline::timeline_ui, ui).expect("Couldn't run timeline_ui system!");
@@ -119,6 +171,9 @@ fn ui(world: &mut World) {
   world.run_system_cached(crate::help::help_dialog_ui).expect("Couldn't run help_dialog_ui system!");
   world.run_system_cached(crate::help::about_dialog_ui).expect("Couldn't run about_dialog_ui system!");
   world.run_system_cached(crate::project::project_settings_dialog_ui).expect("Couldn't run project_settings_dialog_ui system!");
+  world.run_system_cached(crate::background::background_dialog_ui).expect("Couldn't run background_dialog_ui system!");
+  world.run_system_cached(crate::theme::theme_dialog_ui).expect("Couldn't run theme_dialog_ui system!");
+  world.run_system_cached(crate::lyrics_import::lyrics_import_dialog_ui).expect("Couldn't run lyrics_import_dialog_ui system!");
 
   world.run_system_cached(toasts_ui).expect("Couldn't run toasts_ui!");
 }
@@ -181,8 +236,13 @@ fn handle_window_close_requested(mut events: EventReader<WindowCloseRequested>,
   mut exit_confirm_dialog: ResMut<ExitConfirmDialog>,
   editor_state: NonSend<EditorState>,
   mut exit_events: EventWriter<AppExit>,
+  preview_windows: Query<(), With<crate::stage::PreviewWindow>>
 ) {
   for ev in events.read() {
+    // closing the detached preview just puts it back in the editor
+    if preview_windows.contains(ev.window) {
+      continue;
+    }
     if editor_state.needs_save_before_exit {
       exit_confirm_dialog.is_active = true;
     } else {
@@ -108,23 +108,45 @@ fn update_export(mut export_state: ResMut<ExportState>, mut commands: Commands,
         let img_dir = ProjectDirs::from("", "yoteoke", "yoteoke").unwrap().cache_dir().join(Path::new("export"));
         info!("image dir: {:?}", img_dir);
 
-        let song_path: String = String::from(editor_state.project_data.as_ref().unwrap().song_file.as_ref().unwrap().as_os_str().to_string_lossy());
+        // a generated instrumental can stand in for the song
+        let vocal_removal = editor_state.project_data.as_ref().unwrap().vocal_removal.clone().unwrap_or_default();
+        let song_file = editor_state.project_data.as_ref().unwrap().instrumental_file.as_ref()
+          .filter(|_| vocal_removal.use_for_export)
+          .or(editor_state.project_data.as_ref().unwrap().song_file.as_ref());
+        let song_path: String = String::from(song_file.unwrap().as_os_str().to_string_lossy());
 
         let output_path = String::from(export_state.output_path.as_os_str().to_str().unwrap());
 
         let song_delay_time = editor_state.project_data.as_ref().unwrap().song_delay_time.unwrap().to_string();
 
+        // the song file is the instrumental, with the guide vocal optionally 
+        // mixed in quietly
+        let guide_vocal = editor_state.project_data.as_ref().unwrap().guide_vocal.clone().unwrap_or_default();
+        let vocal_path: Option<String> = editor_state.project_data.as_ref().unwrap().vocal_file.as_ref()
+          .filter(|_| guide_vocal.export_guide_vocal)
+          .map(|vocal_file| vocal_file.as_os_str().to_string_lossy().into());
+
         tokio_runtime.spawn_background_task(|_ctx| async move {
           let input_path: String = img_dir.join("%05d.png").as_os_str().to_string_lossy().into();
 
-          let builder = FfmpegBuilder::new()
+          let vocal_mix_filter = format!("[2:a:0]volume={}dB[vocal];[1:a:0][vocal]amix=inputs=2:duration=first:normalize=0[audio]",
+            guide_vocal.export_vocal_level);
+
+          let mut builder = FfmpegBuilder::new()
             .stderr(Stdio::piped())
             .option(Parameter::Single("nostdin"))
             // overwrite file if it exists
             .option(Parameter::Single("y"))
             .option(Parameter::KeyValue("r", "12"))
             .input(File::new(&input_path))
-            .input(File::new(&song_path).option(Parameter::KeyValue("itsoffset", song_delay_time.as_str()))) 
+            .input(File::new(&song_path).option(Parameter::KeyValue("itsoffset", song_delay_time.as_str())));
+          if let Some(vocal_path) = &vocal_path {
+            builder = builder
+              .input(File::new(vocal_path).option(Parameter::KeyValue("itsoffset", song_delay_time.as_str())))
+              .option(Parameter::KeyValue("filter_complex", vocal_mix_filter.as_str()));
+          }
+          let audio_map = if vocal_path.is_some() { "[audio]" } else { "1:a:0" };
+          let builder = builder
             .output(
               // todo: get rid of unwrap
               File::new(output_path.as_str())
@@ -132,7 +154,7 @@ fn update_export(mut export_state: ResMut<ExportState>, mut commands: Commands,
                 .option(Parameter::KeyValue("acodec", "mp3"))
                 .option(Parameter::KeyValue("crf", "25"))
                 .option(Parameter::KeyValue("map", "0:v:0"))  
-                .option(Parameter::KeyValue("map", "1:a:0"))
+                .option(Parameter::KeyValue("map", audio_map))
           );
 
           let ffmpeg = builder.run().await.unwrap();
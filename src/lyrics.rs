@@ -3,16 +3,22 @@ use std::time::Duration;
 use regex::Regex;
 use std::ops::Range;
 use bevy::prelude::*;
+use bevy::color::Srgba;
 use bevy_egui::egui;
 
 use crate::editor::EditorState;
 use crate::editor::show_and_log_error;
+use crate::editor::show_and_log_info;
+use crate::search::{search_bar_ui, LyricsSearch};
+use crate::stage::TextFitState;
+use crate::syllables::{find_next_syllable_start, syllabify_lyrics};
 
 pub struct LyricsPlugin;
 
 impl Plugin for LyricsPlugin {
   fn build(&self, app: &mut App) {
     app.add_systems(Update, update);
+    app.insert_resource(CopiedTiming::default());
   }
 }
 
@@ -29,37 +35,123 @@ fn update(mut editor_state: NonSendMut<EditorState>) {
       }
     }
     editor_state.lyrics_dirty = false;
+    editor_state.lyrics_generation += 1;
   }
 }
 
 pub struct ParsedLyrics {
   pub blocks: Vec<Block>,
+  /// Problems that didn't stop the lyrics from parsing, like unknown tags.
+  pub warnings: Vec<String>,
 }
 
 impl ParsedLyrics {
     pub fn parse(lyrics: &String) -> Result<ParsedLyrics, String> {        
         let mut blocks = Vec::new();
+        let mut warnings = Vec::new();
         let normalized_lyrics = String::from_iter(normalize_line_endings::normalized(lyrics.chars()));
-        let lines = normalized_lyrics.lines();
+        let lines = normalized_lyrics.split('\n');
+
+        let timecode_regex = Regex::new(TIMECODE_PATTERN).unwrap();
+        let style_tag_regex = Regex::new(r"^\[([A-Za-z]+)=([^\]]*)\]$").unwrap();
 
         let mut curr_block = Block::default();
-        for line in lines {
+        // style tags carry on into later lines and blocks until they're changed
+        let mut curr_style = InlineStyle::default();
+        let mut line_offset = 0;
+        for (line_idx, line) in lines.enumerate() {
+            let line_number = line_idx + 1;
+            // byte offset of the trimmed line in the source
+            let line_start = line_offset + line.len() - line.trim_start().len();
+            line_offset += line.len() + 1;
             let line = line.trim();
             assert!(!line.contains("\r"));
             if !line.is_empty() {
+                if curr_block.lyrics.is_empty() {
+                    curr_block.line_range = line_idx..line_idx;
+                    if curr_style != InlineStyle::default() {
+                        curr_block.style_changes.push(StyleChange {
+                            position: 0,
+                            style: curr_style.clone()
+                        });
+                    }
+                }
+                curr_block.line_range.end = line_idx + 1;
                 let (tags, line_without_tags) = Self::extract_tags(line);
+                let (rubies, line_without_ruby, removed_ranges) = Self::extract_ruby(&line_without_tags);
+                let (breaks, line_without_tags, removed_breaks) = Self::extract_syllable_breaks(&line_without_ruby);
+                for ruby in rubies {
+                    let range = Self::remove_ranges_from_position(ruby.range.start, &removed_breaks)
+                        ..Self::remove_ranges_from_position(ruby.range.end, &removed_breaks);
+                    curr_block.ruby.push(Ruby {
+                        range: (range.start + curr_block.lyrics.len())..(range.end + curr_block.lyrics.len()),
+                        text: ruby.text,
+                    });
+                }
+                for syllable_break in breaks {
+                    curr_block.syllable_breaks.push(syllable_break + curr_block.lyrics.len());
+                }
                 for tag in tags {
-                    let timecode_regex = Regex::new(r"\[([0-9]+):([0-9]+).([0-9]+)\]").unwrap();
+                    let position = Self::remove_ranges_from_position(
+                        Self::remove_ranges_from_position(tag.position, &removed_ranges), &removed_breaks)
+                        + curr_block.lyrics.len();
+
+                    if let Some(captures) = style_tag_regex.captures(&tag.tag) {
+                        let key = captures.get(1).unwrap().as_str().to_ascii_lowercase();
+                        let value = captures.get(2).unwrap().as_str().trim();
+                        let result = match key.as_str() {
+                            "singer" => {
+                                curr_style.singers = SingerAssignment::parse(value);
+                                Ok(())
+                            },
+                            "color" => parse_color(value).map(|color| curr_style.sung_color = color),
+                            "unsung" => parse_color(value).map(|color| curr_style.unsung_color = color),
+                            "size" => parse_size(value).map(|size| curr_style.size = size),
+                            // section labels kept by the lyrics import, only there for the reader
+                            "section" => continue,
+                            "pos" => {
+                                // placement is for the whole block, so it doesn't need a style change
+                                match BlockPlacement::parse(value) {
+                                    Ok(placement) => curr_block.placement = placement,
+                                    Err(e) => warnings.push(format!("Line {}: {} in {}", line_number, e, tag.tag)),
+                                }
+                                continue;
+                            },
+                            _ => Err("unknown tag".to_string()),
+                        };
+                        if let Err(e) = result {
+                            warnings.push(format!("Line {}: {} in {}", line_number, e, tag.tag));
+                            continue;
+                        }
+
+                        // a later tag at the same spot replaces an earlier one
+                        curr_block.style_changes.retain(|change| change.position != position);
+                        curr_block.style_changes.push(StyleChange {
+                            position,
+                            style: curr_style.clone()
+                        });
+                        continue;
+                    }
+
                     if let Some(captures) = timecode_regex.captures(&tag.tag) {
-                        let minutes: u32 = captures.get(1).unwrap().as_str().parse().unwrap();
-                        let seconds: u32 = captures.get(2).unwrap().as_str().parse().unwrap();
-                        let millis: u32 = captures.get(3).unwrap().as_str().parse().unwrap();
                         let timestamp = Timestamp {
-                            position: tag.position + curr_block.lyrics.len(),
-                            time: Duration::from_secs_f32(minutes as f32 * 60. + seconds as f32 + millis as f32 / 1000.)
+                            position,
+                            time: get_timecode_time(&captures),
+                            is_syllable_end: is_syllable_end_timecode(&captures),
+                            source_position: line_start + tag.source_position,
                         };
+                        if timestamp.is_syllable_end && curr_block.timestamps.last()
+                            .is_none_or(|prev| prev.is_syllable_end)
+                        {
+                            warnings.push(format!("Line {}: syllable end with no syllable before it in {}", 
+                                line_number, tag.tag));
+                            continue;
+                        }
                         curr_block.timestamps.push(timestamp);
+                        continue;
                     }
+
+                    warnings.push(format!("Line {}: unknown tag in {}", line_number, tag.tag));
                 }
                 curr_block.lyrics.push_str(&line_without_tags);
                 curr_block.lyrics.push_str("\n");
@@ -78,6 +170,7 @@ impl ParsedLyrics {
         
         Ok(ParsedLyrics {
             blocks,
+            warnings,
         })
     }
 
@@ -102,7 +195,8 @@ impl ParsedLyrics {
         let mut tag_len_so_far = 0;
         for range in tag_ranges {
             tags.push(LyricTag {
-                position: *range.start() - tag_len_so_far + 1,
+                position: *range.start() - tag_len_so_far,
+                source_position: *range.start(),
                 tag: line[range.clone()].into()
             });
             tag_len_so_far += range.end() - range.start() + 1;
@@ -111,18 +205,73 @@ impl ParsedLyrics {
         (tags, stripped_line)
     }
 
-    pub fn get_block_at_time(&self, time: &Duration, lead_time: &Duration) -> Option<&Block> {
-        for block in &self.blocks {
-            if let Some(time_range) = block.get_time_range() {
-                let check_range_min = *time;
-                let check_range_max = *time + *lead_time;
-                if time_range.start <= check_range_max && check_range_min <= time_range.end {
-                    return Some(block);
-                }
+    /// Pulls `{base|reading}` ruby out of a line. Also returns the stripped line and removed ranges.
+    fn extract_ruby(line: &str) -> (Vec<Ruby>, String, Vec<Range<usize>>) {
+        let mut rubies = Vec::new();
+        let mut stripped_line = "".to_string();
+        let mut removed_ranges = Vec::new();
+
+        let mut copied_up_to = 0;
+        let mut search_from = 0;
+        while let Some(open) = line[search_from..].find('{').map(|idx| idx + search_from) {
+            let Some(close) = line[open..].find('}').map(|idx| idx + open) else {
+                break;
+            };
+            let Some(bar) = line[open..close].find('|').map(|idx| idx + open) else {
+                // not an annotation, so leave it in the text
+                search_from = open + 1;
+                continue;
+            };
+
+            stripped_line.push_str(&line[copied_up_to..open]);
+            let base_start = stripped_line.len();
+            stripped_line.push_str(&line[(open + 1)..bar]);
+            rubies.push(Ruby {
+                range: base_start..stripped_line.len(),
+                text: line[(bar + 1)..close].trim().into(),
+            });
+            removed_ranges.push(open..(open + 1));
+            removed_ranges.push(bar..(close + 1));
+
+            copied_up_to = close + 1;
+            search_from = close + 1;
+        }
+        stripped_line.push_str(&line[copied_up_to..]);
+
+        (rubies, stripped_line, removed_ranges)
+    }
+
+    /// Pulls `|` syllable breaks out of a line. Also returns the stripped line and removed ranges.
+    fn extract_syllable_breaks(line: &str) -> (Vec<usize>, String, Vec<Range<usize>>) {
+        let mut breaks = Vec::new();
+        let mut removed_ranges = Vec::new();
+        let mut stripped_line = "".to_string();
+        for (idx, c) in line.char_indices() {
+            if c == SYLLABLE_BREAK {
+                breaks.push(stripped_line.len());
+                removed_ranges.push(idx..(idx + c.len_utf8()));
+            } else {
+                stripped_line.push(c);
             }
         }
-        
-        None
+
+        (breaks, stripped_line, removed_ranges)
+    }
+
+    /// Moves a position back by however much was removed before it.
+    fn remove_ranges_from_position(position: usize, removed_ranges: &[Range<usize>]) -> usize {
+        let removed_before: usize = removed_ranges.iter()
+            .map(|range| position.min(range.end).saturating_sub(range.start))
+            .sum();
+        position - removed_before
+    }
+
+    /// If more than one block is being sung, gets the one that started last.
+    pub fn get_block_sung_at(&self, time: &Duration) -> Option<&Block> {
+        self.blocks.iter()
+            .filter(|block| block.get_time_range()
+                .is_some_and(|range| range.start <= *time && *time <= range.end))
+            .max_by_key(|block| block.start_time())
     }
 }
 
@@ -130,27 +279,28 @@ impl ParsedLyrics {
 pub struct Block {
     pub lyrics: String,
     pub timestamps: Vec<Timestamp>,
+    pub style_changes: Vec<StyleChange>,
+    pub placement: Option<BlockPlacement>,
+    pub ruby: Vec<Ruby>,
+    pub line_range: Range<usize>,
+    pub syllable_breaks: Vec<usize>,
 }
 
 impl Block {
+    /// Empty for a block with a single timestamp.
     pub fn get_time_range(&self) -> Option<Range<Duration>> {
-        let mut first_timestamp = None;
-        let mut last_timestamp = None;
-
-        for timestamp in &self.timestamps {
-            if first_timestamp.is_none() {
-                first_timestamp = Some(timestamp);
-            } else {
-                last_timestamp = Some(timestamp);
-            }
-        }
+        Some(self.start_time()?..self.end_time()?)
+    }
 
-        if let Some(first_timestamp) = first_timestamp {
-            if let Some(last_timestamp) = last_timestamp {
-                return Some(first_timestamp.time..last_timestamp.time);
-            }
-        }
-        None
+    /// Shows the block `pre_roll` before it's sung until `post_roll` after.
+    pub fn get_display_window(&self, pre_roll: &Duration, post_roll: &Duration) -> Option<DisplayWindow> {
+        let time_range = self.get_time_range()?;
+        Some(DisplayWindow {
+            appear: time_range.start.saturating_sub(*pre_roll),
+            first_sung: time_range.start,
+            last_sung: time_range.end,
+            disappear: time_range.end + *post_roll,
+        })
     }
 
     pub fn start_time(&self) -> Option<Duration> {
@@ -169,66 +319,465 @@ impl Block {
         None
     }
 
-    pub fn get_timestamps_surrounding(&self, time: &Duration) -> 
-      Option<(Timestamp, Timestamp)> 
-    {
-        if let Some(start_time) = self.start_time() {
-            if let Some(end_time) = self.end_time() {
-                if time < &start_time || time > &end_time {
-                    return None
-                }
+    /// Untimed syllable breaks split a syllable into equal parts.
+    pub fn get_syllables(&self) -> Vec<Syllable> {
+        self.timestamps.windows(2)
+            .filter(|pair| !pair[0].is_syllable_end)
+            .flat_map(|pair| {
+                let (start, end) = (&pair[0], &pair[1]);
+                let mut bounds = vec![start.position];
+                bounds.extend(self.syllable_breaks.iter()
+                    .filter(|position| start.position < **position && **position < end.position));
+                bounds.push(end.position);
 
-                for (idx, timestamp) in self.timestamps.iter().enumerate() {
-                    if timestamp.time > *time  {
-                        if idx == 0 {
-                            return None
-                        }
+                let part_count = bounds.len() as u32 - 1;
+                let duration = end.time.saturating_sub(start.time);
+                bounds.windows(2)
+                    .enumerate()
+                    .map(|(idx, part)| Syllable {
+                        range: part[0]..part[1],
+                        start: start.time + duration * idx as u32 / part_count,
+                        end: start.time + duration * (idx as u32 + 1) / part_count,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+}
 
-                        return Some((self.timestamps[idx - 1].clone(), 
-                            self.timestamps[idx].clone()));
-                    }
-                }
-            }
-        }
+/// When a block is on screen and when it's sung, in song time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DisplayWindow {
+    pub appear: Duration,
+    pub first_sung: Duration,
+    pub last_sung: Duration,
+    pub disappear: Duration,
+}
 
-        None
+#[derive(Clone, Debug, PartialEq)]
+pub struct Syllable {
+    pub range: Range<usize>,
+    pub start: Duration,
+    pub end: Duration,
+}
+
+impl Syllable {
+    pub fn duration(&self) -> Duration {
+        self.end.saturating_sub(self.start)
     }
 }
 
 #[derive(Debug)]
 struct LyricTag {
     position: usize,
+    source_position: usize,
     tag: String
 }
 
+/// Who sings a part of the lyrics, from a `[singer=...]` tag.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SingerAssignment {
+    /// `both` or `all`.
+    All,
+    /// 1-based numbers or names, e.g. `1+2` or `Alex & Sam`.
+    Singers(Vec<String>),
+}
+
+impl SingerAssignment {
+    fn parse(value: &str) -> Option<SingerAssignment> {
+        let value = value.trim();
+        if value.is_empty() || value.eq_ignore_ascii_case("none") {
+            return None;
+        }
+        if value.eq_ignore_ascii_case("both") || value.eq_ignore_ascii_case("all") {
+            return Some(SingerAssignment::All);
+        }
+        Some(SingerAssignment::Singers(value.split(['+', '&'])
+            .map(|singer| singer.trim().to_string())
+            .filter(|singer| !singer.is_empty())
+            .collect()))
+    }
+}
+
+/// Style set by inline tags. `None` uses the project's settings.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InlineStyle {
+    pub singers: Option<SingerAssignment>,
+    pub sung_color: Option<Color>,
+    pub unsung_color: Option<Color>,
+    pub size: Option<f32>,
+}
+
+#[derive(Clone, Debug)]
+pub struct StyleChange {
+    pub position: usize,
+    pub style: InlineStyle,
+}
+
+/// Reading text shown above `range`, written as `{漢字|かんじ}`.
+#[derive(Clone, Debug)]
+pub struct Ruby {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlockPlacement {
+    Top,
+    Center,
+    Bottom,
+}
+
+impl BlockPlacement {
+    fn parse(value: &str) -> Result<Option<BlockPlacement>, String> {
+        match value.to_ascii_lowercase().as_str() {
+            "" | "none" => Ok(None),
+            "top" => Ok(Some(BlockPlacement::Top)),
+            "center" | "middle" => Ok(Some(BlockPlacement::Center)),
+            "bottom" => Ok(Some(BlockPlacement::Bottom)),
+            _ => Err("invalid position".into()),
+        }
+    }
+}
+
+fn parse_color(value: &str) -> Result<Option<Color>, String> {
+    if value.is_empty() || value.eq_ignore_ascii_case("none") {
+        return Ok(None);
+    }
+    Srgba::hex(value)
+        .map(|color| Some(color.into()))
+        .map_err(|_| "invalid color".into())
+}
+
+fn parse_size(value: &str) -> Result<Option<f32>, String> {
+    if value.is_empty() || value.eq_ignore_ascii_case("none") {
+        return Ok(None);
+    }
+    match value.parse::<f32>() {
+        Ok(size) if size > 0. => Ok(Some(size)),
+        _ => Err("invalid size".into()),
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Timestamp {
     pub position: usize,
-    pub time: Duration
+    pub time: Duration,
+    /// Ends the syllable before it rather than starting one, from `[mm:ss.mmm>]`.
+    pub is_syllable_end: bool,
+    pub source_position: usize,
+}
+
+pub const SYLLABLE_BREAK: char = '|';
+
+/// `[mm:ss.mmm]`, or `[mm:ss.mmm>]` for a syllable end.
+pub const TIMECODE_PATTERN: &str = r"\[([0-9]+):([0-9]+).([0-9]+)(>?)\]";
+
+pub fn get_timecode_time(captures: &regex::Captures) -> Duration {
+    let minutes: u32 = captures.get(1).unwrap().as_str().parse().unwrap();
+    let seconds: u32 = captures.get(2).unwrap().as_str().parse().unwrap();
+    let millis: u32 = captures.get(3).unwrap().as_str().parse().unwrap();
+    Duration::from_secs_f32(minutes as f32 * 60. + seconds as f32 + millis as f32 / 1000.)
+}
+
+pub fn is_syllable_end_timecode(captures: &regex::Captures) -> bool {
+    captures.get(4).is_some_and(|marker| !marker.is_empty())
+}
+
+pub fn format_timecode(time: &Duration) -> String {
+    format!("[{:0>2}:{:0>2}.{:0>3}]", time.as_secs() / 60, time.as_secs() % 60, time.subsec_millis())
+}
+
+pub fn format_syllable_end_timecode(time: &Duration) -> String {
+    format!("[{:0>2}:{:0>2}.{:0>3}>]", time.as_secs() / 60, time.as_secs() % 60, time.subsec_millis())
+}
+
+/// Gets the byte range of each word in lyrics source, without tags.
+pub fn get_source_words(source: &str) -> Vec<Range<usize>> {
+    let mut words: Vec<Range<usize>> = Vec::new();
+    let mut in_tag = false;
+    let mut in_word = false;
+    for (idx, c) in source.char_indices() {
+        if in_tag {
+            in_tag = c != ']';
+        } else if c == '[' {
+            in_tag = true;
+        } else if c.is_whitespace() {
+            in_word = false;
+        } else {
+            if in_word {
+                words.last_mut().unwrap().end = idx + c.len_utf8();
+            } else {
+                words.push(idx..(idx + c.len_utf8()));
+            }
+            in_word = true;
+        }
+    }
+    words
+}
+
+/// Gets lyrics source without tags, syllable breaks or ruby readings.
+pub fn get_displayed_text(source: &str) -> String {
+    let mut displayed = String::with_capacity(source.len());
+    let mut in_tag = false;
+    let mut in_braces = false;
+    let mut in_reading = false;
+    for c in source.chars() {
+        if in_tag {
+            in_tag = c != ']';
+        } else if c == '[' {
+            in_tag = true;
+        } else if c == '{' {
+            in_braces = true;
+        } else if c == '}' {
+            (in_braces, in_reading) = (false, false);
+        } else if c == SYLLABLE_BREAK {
+            in_reading = in_braces;
+        } else if !in_reading {
+            displayed.push(c);
+        }
+    }
+    displayed
+}
+
+/// Returns the new lyrics and how many timestamps changed.
+pub fn retime_timecodes(lyrics: &str, mut retime: impl FnMut(Duration) -> Option<Duration>) 
+    -> (String, usize) 
+{
+    let timecode_regex = Regex::new(TIMECODE_PATTERN).unwrap();
+    let mut changed_count = 0;
+    let retimed_lyrics = timecode_regex.replace_all(lyrics, |captures: &regex::Captures| {
+        let Some(new_time) = retime(get_timecode_time(captures)) else {
+            return captures[0].to_string();
+        };
+        let new_timecode = if is_syllable_end_timecode(captures) {
+            format_syllable_end_timecode(&new_time)
+        } else {
+            format_timecode(&new_time)
+        };
+        if new_timecode != captures[0] {
+            changed_count += 1;
+        }
+        new_timecode
+    });
+    (retimed_lyrics.into_owned(), changed_count)
+}
+
+fn char_to_byte_index(text: &str, char_index: usize) -> usize {
+    text.char_indices().nth(char_index).map(|(idx, _)| idx).unwrap_or(text.len())
+}
+
+fn byte_to_char_index(text: &str, byte_index: usize) -> usize {
+    text[..byte_index.min(text.len())].chars().count()
+}
+
+#[derive(Resource, Default)]
+pub struct CopiedTiming {
+    source: Option<String>,
+}
+
+/// Needs the same text or the same number of timestamps in both blocks.
+pub fn paste_block_timing(lyrics: &str, copied_source: &str, target_lines: Range<usize>, 
+    start_time: Duration) -> Result<String, String> 
+{
+    let timecode_regex = Regex::new(TIMECODE_PATTERN).unwrap();
+    let copied_timestamps = timecode_regex.captures_iter(copied_source)
+        .map(|captures| (get_timecode_time(&captures), is_syllable_end_timecode(&captures)))
+        .collect::<Vec<_>>();
+    let Some((first_time, _)) = copied_timestamps.first().cloned() else {
+        return Err("The copied block has no timestamps".into());
+    };
+    let retime = |time: Duration| start_time + time.saturating_sub(first_time);
+
+    let mut lines = lyrics.split('\n').map(String::from).collect::<Vec<_>>();
+    let Some(target) = lines.get_mut(target_lines) else {
+        return Err("The block is out of date with the lyrics".into());
+    };
+    let target_source = target.join("\n");
+    let strip_timecodes = |source: &str| source.split('\n')
+        .map(|line| timecode_regex.replace_all(line.trim(), "").into_owned())
+        .collect::<Vec<_>>();
+
+    if strip_timecodes(copied_source) == strip_timecodes(&target_source) {
+        let (retimed_source, _) = retime_timecodes(copied_source, |time| Some(retime(time)));
+        for (line, retimed_line) in target.iter_mut().zip(retimed_source.split('\n')) {
+            *line = retimed_line.trim().to_string();
+        }
+    } else if timecode_regex.find_iter(&target_source).count() == copied_timestamps.len() {
+        let mut copied_iter = copied_timestamps.iter();
+        for line in target.iter_mut() {
+            *line = timecode_regex.replace_all(line, |_: &regex::Captures| {
+                let (time, is_syllable_end) = copied_iter.next().unwrap();
+                if *is_syllable_end {
+                    format_syllable_end_timecode(&retime(*time))
+                } else {
+                    format_timecode(&retime(*time))
+                }
+            }).into_owned();
+        }
+    } else {
+        return Err("The blocks need the same text or the same number of timestamps".into());
+    }
+
+    Ok(lines.join("\n"))
 }
 
 pub fn lyrics_edit_ui(mut ui: InMut<egui::Ui>, 
   mut editor_state: NonSendMut<EditorState>,
-  mut audio_state: NonSendMut<crate::editor::AudioState>
+  mut audio_state: NonSendMut<crate::editor::AudioState>,
+  mut lyrics_search: ResMut<LyricsSearch>,
+  mut copied_timing: ResMut<CopiedTiming>,
+  text_fit_state: Res<TextFitState>
 ) {
+  let lyrics_edit_id = egui::Id::new("lyrics_edit");
   let mut text_edit_changed = false;
   let mut cursor_pos = None;
   let mut insert_desired = false;
+  let mut insert_end_desired = false;
+  let mut tap_desired = false;
+  // byte range of the lyrics to select and scroll to
+  let mut new_selection: Option<Range<usize>> = None;
+  let mut seek_time = None;
+  let mut replaced_count = 0;
+  let mut paste_result = None;
+  let mut syllabified_count = None;
   let curr_time = audio_state.playhead_position();
   let needs_save_before_exit = editor_state.needs_save_before_exit;
+  let parsed_lyrics_warnings = editor_state.parsed_lyrics.as_ref()
+    .map(|parsed_lyrics| parsed_lyrics.warnings.clone());
+  let timestamps = editor_state.parsed_lyrics.as_ref()
+    .map(|parsed_lyrics| parsed_lyrics.blocks.iter()
+      .flat_map(|block| block.timestamps.iter())
+      .map(|timestamp| (timestamp.source_position, timestamp.time))
+      .collect::<Vec<_>>())
+    .unwrap_or_default();
+  let block_line_ranges = editor_state.parsed_lyrics.as_ref()
+    .map(|parsed_lyrics| parsed_lyrics.blocks.iter()
+      .map(|block| block.line_range.clone())
+      .collect::<Vec<_>>())
+    .unwrap_or_default();
   if let Some(project_data) = &mut editor_state.project_data {
+    let selection = egui::text_edit::TextEditState::load(ui.ctx(), lyrics_edit_id)
+      .and_then(|text_edit_state| text_edit_state.cursor.char_range())
+      .map(|char_range| {
+        let primary = char_to_byte_index(&project_data.lyrics, char_range.primary.index);
+        let secondary = char_to_byte_index(&project_data.lyrics, char_range.secondary.index);
+        primary.min(secondary)..primary.max(secondary)
+      });
+
     let mut title_str = format!("{} - {}", project_data.artist, project_data.title);
     if needs_save_before_exit {
       title_str += "*";
     }
     ui.label(title_str);
-    if ui.button("Insert").clicked() {
-      insert_desired = true;
+    ui.horizontal(|ui| {
+      if ui.button("Insert").clicked() {
+        insert_desired = true;
+      }
+      if ui.button("Insert End").on_hover_text("Insert a syllable end at the playhead").clicked() {
+        insert_end_desired = true;
+      }
+      if ui.button("Tap").on_hover_text("Time the next syllable after the cursor at the playhead (Ctrl+Enter)").clicked() {
+        tap_desired = true;
+      }
+      if ui.button("Jump to Playhead").on_hover_text("Move the cursor to the timestamp being sung").clicked() {
+        // the latest timestamp that's been reached, or the first if none have
+        new_selection = timestamps.iter()
+          .filter(|(_, time)| *time <= curr_time)
+          .max_by_key(|(position, time)| (*time, std::cmp::Reverse(*position)))
+          .or(timestamps.first())
+          .map(|(position, _)| *position..*position);
+      }
+      if ui.button("Seek to Cursor").on_hover_text("Move the playhead to the timestamp before the cursor").clicked() {
+        if let Some(selection) = &selection {
+          seek_time = timestamps.iter()
+            .take_while(|(position, _)| *position <= selection.start)
+            .last()
+            .or(timestamps.first())
+            .map(|(_, time)| *time);
+        }
+      }
+      ui.toggle_value(&mut lyrics_search.is_open, "Find");
+    });
+    // the lines of the block the cursor is in
+    let cursor_block_lines = selection.as_ref().and_then(|selection| {
+      let line_idx = project_data.lyrics[..selection.start].matches('\n').count();
+      block_line_ranges.iter().find(|line_range| line_range.contains(&line_idx)).cloned()
+    });
+    ui.horizontal(|ui| {
+      if ui.add_enabled(cursor_block_lines.is_some(), egui::Button::new("Copy Timing"))
+        .on_hover_text("Copy the timing of the block the cursor is in")
+        .clicked() 
+      {
+        if let Some(line_range) = &cursor_block_lines {
+          copied_timing.source = project_data.lyrics.split('\n').collect::<Vec<_>>()
+            .get(line_range.clone())
+            .map(|lines| lines.join("\n"));
+        }
+      }
+      if ui.add_enabled(cursor_block_lines.is_some() && copied_timing.source.is_some(), egui::Button::new("Paste Timing"))
+        .on_hover_text("Give the block the cursor is in the copied timing, starting at the playhead")
+        .clicked() 
+      {
+        if let (Some(line_range), Some(copied_source)) = (&cursor_block_lines, &copied_timing.source) {
+          paste_result = Some(paste_block_timing(&project_data.lyrics, copied_source, line_range.clone(), curr_time));
+        }
+      }
+      if ui.button("Syllabify")
+        .on_hover_text("Split the words of untimed blocks into syllables with |")
+        .clicked()
+      {
+        let language = project_data.syllabification.clone().unwrap_or_default().language;
+        let (lyrics, break_count) = syllabify_lyrics(&project_data.lyrics, language);
+        project_data.lyrics = lyrics;
+        syllabified_count = Some(break_count);
+      }
+    });
+    if ui.input_mut(|i| i.consume_shortcut(&egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::F))) {
+      lyrics_search.is_open = true;
+    }
+    if ui.input_mut(|i| i.consume_shortcut(&egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Enter))) {
+      tap_desired = true;
+    }
+    if tap_desired {
+      let from = selection.as_ref().map(|selection| selection.end).unwrap_or(0);
+      if let Some(range) = find_next_syllable_start(&project_data.lyrics, from) {
+        let timecode = format_timecode(&curr_time);
+        let after_timecode = range.start + timecode.len();
+        project_data.lyrics.replace_range(range, &timecode);
+        new_selection = Some(after_timecode..after_timecode);
+        text_edit_changed = true;
+      }
+    }
+    if lyrics_search.is_open {
+      let result = search_bar_ui(&mut ui, &mut lyrics_search, &mut project_data.lyrics, selection.clone());
+      if result.selection.is_some() {
+        new_selection = result.selection;
+      }
+      if result.replaced_count > 0 {
+        replaced_count = result.replaced_count;
+        text_edit_changed = true;
+      }
+    }
+    if let Some(warnings) = &parsed_lyrics_warnings {
+      for warning in warnings {
+        ui.colored_label(egui::Color32::YELLOW, warning);
+      }
+    }
+    for warning in &text_fit_state.warnings {
+      ui.colored_label(egui::Color32::YELLOW, warning);
     }
     ui.separator();
-    egui::ScrollArea::both().show(&mut ui, |ui| {
+    let mut scroll_area = egui::ScrollArea::both();
+    if let Some(new_selection) = &new_selection {
+      // put the selected line in the middle of the editor
+      let line_idx = project_data.lyrics[..new_selection.start].matches('\n').count();
+      let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+      scroll_area = scroll_area.vertical_scroll_offset((line_idx as f32 * row_height - ui.available_height() / 2.).max(0.));
+    }
+    scroll_area.show(&mut ui, |ui| {
       let text_edit_response = ui.add_sized(ui.available_size(), 
-        egui::TextEdit::multiline(&mut project_data.lyrics).code_editor());
+        egui::TextEdit::multiline(&mut project_data.lyrics).id(lyrics_edit_id).code_editor());
       if text_edit_response.changed() {
         info!("text edit changed");
         text_edit_changed = true;
@@ -241,20 +790,339 @@ pub fn lyrics_edit_ui(mut ui: InMut<egui::Ui>,
         }
       }
     });
-    if insert_desired {
+    if let Some(new_selection) = new_selection {
+      let mut text_edit_state = egui::text_edit::TextEditState::load(ui.ctx(), lyrics_edit_id).unwrap_or_default();
+      text_edit_state.cursor.set_char_range(Some(egui::text::CCursorRange::two(
+        egui::text::CCursor::new(byte_to_char_index(&project_data.lyrics, new_selection.start)),
+        egui::text::CCursor::new(byte_to_char_index(&project_data.lyrics, new_selection.end))
+      )));
+      text_edit_state.store(ui.ctx(), lyrics_edit_id);
+      ui.memory_mut(|memory| memory.request_focus(lyrics_edit_id));
+    }
+    if insert_desired || insert_end_desired {
       if let Some(cursor_pos) = cursor_pos {
-        let str_to_insert = format!("[{:0>2}:{:0>2}.{:0>3}]", 
-          curr_time.as_secs() / 60, curr_time.as_secs() % 60, curr_time.subsec_millis());
-        project_data.lyrics.insert_str(cursor_pos.index, &str_to_insert);
+        let str_to_insert = if insert_end_desired {
+          format_syllable_end_timecode(&curr_time)
+        } else {
+          format_timecode(&curr_time)
+        };
+        let insert_idx = char_to_byte_index(&project_data.lyrics, cursor_pos.index);
+        project_data.lyrics.insert_str(insert_idx, &str_to_insert);
         text_edit_changed = true
       }
     }
     // hack: keep carriage returns from entering lyrics
     project_data.lyrics = project_data.lyrics.replace("\r", "");
   }
+  if let Some(seek_time) = seek_time {
+    let delay_time = editor_state.project_data.as_ref()
+      .and_then(|project_data| project_data.song_delay_time)
+      .unwrap_or_default();
+    crate::timeline::seek_playhead_to(editor_state.reborrow(), audio_state.reborrow(),
+      seek_time.as_secs_f64() + delay_time as f64);
+  }
+  match paste_result {
+    Some(Ok(lyrics)) => {
+      editor_state.project_data.as_mut().unwrap().lyrics = lyrics;
+      text_edit_changed = true;
+    },
+    Some(Err(e)) => show_and_log_error(editor_state.as_mut(), format!("Couldn't paste timing: {}", e)),
+    None => {}
+  }
+  if let Some(break_count) = syllabified_count {
+    if break_count > 0 {
+      text_edit_changed = true;
+    }
+    show_and_log_info(editor_state.as_mut(), format!("Added {} syllable breaks", break_count));
+  }
+  if replaced_count > 0 {
+    show_and_log_info(editor_state.as_mut(), format!("Replaced {} matches", replaced_count));
+  }
   if text_edit_changed {
     info!("lyrics marked dirty");
     editor_state.lyrics_dirty = true;
     editor_state.needs_save_before_exit = true;
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(lyrics: &str) -> ParsedLyrics {
+        ParsedLyrics::parse(&lyrics.to_string()).unwrap()
+    }
+
+    fn secs(secs: f32) -> Duration {
+        Duration::from_secs_f32(secs)
+    }
+
+    #[test]
+    fn splits_blocks_on_empty_lines() {
+        let lyrics = parse("[00:01.000]one\n[00:02.000]two\n\n\n[00:03.000]three\n");
+        assert_eq!(lyrics.blocks.len(), 2);
+        assert_eq!(lyrics.blocks[0].lyrics, "one\ntwo\n");
+        assert_eq!(lyrics.blocks[0].line_range, 0..2);
+        assert_eq!(lyrics.blocks[1].lyrics, "three\n");
+        assert_eq!(lyrics.blocks[1].line_range, 4..5);
+    }
+
+    #[test]
+    fn time_range_spans_first_to_last_timestamp() {
+        let lyrics = parse("[00:01.000]Hello [00:02.000]world[00:03.500]");
+        let block = &lyrics.blocks[0];
+        assert_eq!(block.lyrics, "Hello world\n");
+        assert_eq!(block.get_time_range(), Some(secs(1.)..secs(3.5)));
+    }
+
+    #[test]
+    fn single_timestamp_block_is_displayed() {
+        let lyrics = parse("[00:10.000]Hey!");
+        let block = &lyrics.blocks[0];
+        assert_eq!(block.get_time_range(), Some(secs(10.)..secs(10.)));
+
+        let window = block.get_display_window(&secs(2.), &secs(1.)).unwrap();
+        assert_eq!(window, DisplayWindow {
+            appear: secs(8.),
+            first_sung: secs(10.),
+            last_sung: secs(10.),
+            disappear: secs(11.),
+        });
+    }
+
+    #[test]
+    fn untimed_block_is_never_displayed() {
+        let lyrics = parse("no timestamps here");
+        assert!(lyrics.blocks[0].get_time_range().is_none());
+        assert!(lyrics.blocks[0].get_display_window(&secs(3.), &secs(1.)).is_none());
+    }
+
+    #[test]
+    fn display_window_does_not_start_before_zero() {
+        let lyrics = parse("[00:01.000]early[00:02.000]");
+        let window = lyrics.blocks[0].get_display_window(&secs(3.), &secs(0.)).unwrap();
+        assert_eq!(window.appear, Duration::ZERO);
+    }
+
+    #[test]
+    fn latest_started_block_is_sung() {
+        let lyrics = parse("[00:01.000]lead vocal[00:05.000]\n\n[00:03.000]backing[00:06.000]");
+        assert_eq!(lyrics.get_block_sung_at(&secs(2.)).unwrap().lyrics, "lead vocal\n");
+        assert_eq!(lyrics.get_block_sung_at(&secs(4.)).unwrap().lyrics, "backing\n");
+        assert!(lyrics.get_block_sung_at(&secs(7.)).is_none());
+    }
+
+    #[test]
+    fn syllable_ends_leave_gaps() {
+        let lyrics = parse("[00:01.000]Hold[00:02.000>] on[00:03.000]");
+        let block = &lyrics.blocks[0];
+        assert_eq!(block.get_syllables(), vec![
+            Syllable { range: 0..4, start: secs(1.), end: secs(2.) },
+        ]);
+
+        let lyrics = parse("[00:01.000]Hold[00:02.000>] [00:02.500]on[00:03.000]");
+        let syllables = lyrics.blocks[0].get_syllables();
+        assert_eq!(syllables.len(), 2);
+        assert_eq!(syllables[1], Syllable { range: 5..7, start: secs(2.5), end: secs(3.) });
+    }
+
+    #[test]
+    fn syllable_breaks_split_time_evenly() {
+        let lyrics = parse("[00:01.000]syl|la|ble[00:04.000]");
+        let block = &lyrics.blocks[0];
+        assert_eq!(block.lyrics, "syllable\n");
+        assert_eq!(block.syllable_breaks, vec![3, 5]);
+        assert_eq!(block.get_syllables(), vec![
+            Syllable { range: 0..3, start: secs(1.), end: secs(2.) },
+            Syllable { range: 3..5, start: secs(2.), end: secs(3.) },
+            Syllable { range: 5..8, start: secs(3.), end: secs(4.) },
+        ]);
+    }
+
+    #[test]
+    fn syllable_end_without_syllable_warns() {
+        let lyrics = parse("[00:01.000>]oops[00:02.000]");
+        assert_eq!(lyrics.blocks[0].timestamps.len(), 1);
+        assert_eq!(lyrics.warnings, vec!["Line 1: syllable end with no syllable before it in [00:01.000>]".to_string()]);
+    }
+
+    #[test]
+    fn unknown_tags_warn_with_line_number() {
+        let lyrics = parse("first\n[bogus]second");
+        assert_eq!(lyrics.blocks[0].lyrics, "first\nsecond\n");
+        assert_eq!(lyrics.warnings, vec!["Line 2: unknown tag in [bogus]".to_string()]);
+    }
+
+    #[test]
+    fn tag_positions_are_byte_indices_in_block() {
+        let lyrics = parse("[00:01.000]ab[00:02.000]cd");
+        let block = &lyrics.blocks[0];
+        assert_eq!(block.lyrics, "abcd\n");
+        assert_eq!(block.timestamps.iter().map(|timestamp| timestamp.position).collect::<Vec<_>>(), vec![0, 2]);
+        assert_eq!(block.timestamps.iter().map(|timestamp| timestamp.source_position).collect::<Vec<_>>(), vec![0, 13]);
+    }
+
+    #[test]
+    fn color_tags_change_style() {
+        let lyrics = parse("[color=#ff0000]red [unsung=#00ff00]mixed [color=none]plain");
+        let changes = &lyrics.blocks[0].style_changes;
+        assert_eq!(changes.iter().map(|change| change.position).collect::<Vec<_>>(), vec![0, 4, 10]);
+        assert_eq!(changes[0].style.sung_color, Some(Color::srgb(1., 0., 0.)));
+        assert_eq!(changes[1].style.sung_color, Some(Color::srgb(1., 0., 0.)));
+        assert_eq!(changes[1].style.unsung_color, Some(Color::srgb(0., 1., 0.)));
+        assert_eq!(changes[2].style.sung_color, None);
+        assert_eq!(changes[2].style.unsung_color, Some(Color::srgb(0., 1., 0.)));
+        assert!(lyrics.warnings.is_empty());
+    }
+
+    #[test]
+    fn style_carries_into_later_blocks() {
+        let lyrics = parse("[size=1.5]big\n\nstill big[size=]\nnormal");
+        assert_eq!(lyrics.blocks[0].style_changes[0].style.size, Some(1.5));
+        let changes = &lyrics.blocks[1].style_changes;
+        assert_eq!(changes.iter().map(|change| (change.position, change.style.size)).collect::<Vec<_>>(),
+            vec![(0, Some(1.5)), (9, None)]);
+    }
+
+    #[test]
+    fn pos_tags_place_block() {
+        let lyrics = parse("[pos=top]up here\n\n[pos=middle]and here\n\nnowhere");
+        assert_eq!(lyrics.blocks[0].placement, Some(BlockPlacement::Top));
+        assert!(lyrics.blocks[0].style_changes.is_empty());
+        assert_eq!(lyrics.blocks[1].placement, Some(BlockPlacement::Center));
+        assert_eq!(lyrics.blocks[2].placement, None);
+    }
+
+    #[test]
+    fn invalid_tag_values_warn() {
+        let lyrics = parse("[color=blue]one\n[size=-1]two\n[pos=sideways]three\n[font=Arial]four");
+        assert_eq!(lyrics.blocks[0].lyrics, "one\ntwo\nthree\nfour\n");
+        assert!(lyrics.blocks[0].style_changes.is_empty());
+        assert_eq!(lyrics.blocks[0].placement, None);
+        assert_eq!(lyrics.warnings, vec![
+            "Line 1: invalid color in [color=blue]".to_string(),
+            "Line 2: invalid size in [size=-1]".to_string(),
+            "Line 3: invalid position in [pos=sideways]".to_string(),
+            "Line 4: unknown tag in [font=Arial]".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn section_tags_are_ignored() {
+        let lyrics = parse("[section=Chorus]first\nsecond");
+        assert_eq!(lyrics.blocks[0].lyrics, "first\nsecond\n");
+        assert!(lyrics.warnings.is_empty());
+        assert!(lyrics.blocks[0].style_changes.is_empty());
+    }
+
+    #[test]
+    fn parses_singer_tags() {
+        let singers = |names: &[&str]| {
+            Some(SingerAssignment::Singers(names.iter().map(|name| name.to_string()).collect()))
+        };
+        assert_eq!(SingerAssignment::parse("Alex"), singers(&["Alex"]));
+        assert_eq!(SingerAssignment::parse(" Alex & Sam "), singers(&["Alex", "Sam"]));
+        assert_eq!(SingerAssignment::parse("1+2"), singers(&["1", "2"]));
+        // a stray separator doesn't make an empty singer
+        assert_eq!(SingerAssignment::parse("Alex &"), singers(&["Alex"]));
+        assert_eq!(SingerAssignment::parse("BOTH"), Some(SingerAssignment::All));
+        assert_eq!(SingerAssignment::parse("all"), Some(SingerAssignment::All));
+        assert_eq!(SingerAssignment::parse(""), None);
+        assert_eq!(SingerAssignment::parse("  "), None);
+        assert_eq!(SingerAssignment::parse("None"), None);
+    }
+
+    #[test]
+    fn singer_tags_set_style() {
+        let lyrics = parse("[singer=Alex & Sam]together[singer=]alone");
+        let changes = &lyrics.blocks[0].style_changes;
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].style.singers, Some(SingerAssignment::Singers(vec!["Alex".into(), "Sam".into()])));
+        assert_eq!((changes[1].position, &changes[1].style.singers), ("together".len(), &None));
+    }
+
+    fn ruby_spans(rubies: &[Ruby]) -> Vec<(Range<usize>, &str)> {
+        rubies.iter().map(|ruby| (ruby.range.clone(), ruby.text.as_str())).collect()
+    }
+
+    #[test]
+    fn extracts_ruby_on_multibyte_text() {
+        let (rubies, stripped_line, removed_ranges) = ParsedLyrics::extract_ruby("{漢字|かんじ}です");
+        assert_eq!(stripped_line, "漢字です");
+        assert_eq!(ruby_spans(&rubies), vec![(0..6, "かんじ")]);
+        // the brace, and the bar through the closing brace
+        assert_eq!(removed_ranges, vec![0..1, 7..18]);
+    }
+
+    #[test]
+    fn extracts_several_ruby_on_a_line() {
+        let (rubies, stripped_line, _) = ParsedLyrics::extract_ruby("{今日|きょう}は{晴|は}れ");
+        assert_eq!(stripped_line, "今日は晴れ");
+        assert_eq!(ruby_spans(&rubies), vec![(0..6, "きょう"), (9..12, "は")]);
+    }
+
+    #[test]
+    fn braces_without_ruby_stay_in_text() {
+        let (rubies, stripped_line, removed_ranges) = ParsedLyrics::extract_ruby("{漢字|かんじ");
+        assert_eq!(stripped_line, "{漢字|かんじ");
+        assert!(rubies.is_empty());
+        assert!(removed_ranges.is_empty());
+
+        let (rubies, stripped_line, _) = ParsedLyrics::extract_ruby("{aside} {a|b}");
+        assert_eq!(stripped_line, "{aside} a");
+        assert_eq!(ruby_spans(&rubies), vec![(8..9, "b")]);
+    }
+
+    #[test]
+    fn ruby_next_to_timestamps() {
+        let lyrics = parse("[00:01.000]{漢字|かんじ}[00:02.000]です[00:03.000]");
+        let block = &lyrics.blocks[0];
+        assert_eq!(block.lyrics, "漢字です\n");
+        assert_eq!(ruby_spans(&block.ruby), vec![(0..6, "かんじ")]);
+        assert_eq!(block.timestamps.iter().map(|timestamp| timestamp.position).collect::<Vec<_>>(), vec![0, 6, 12]);
+    }
+
+    const COPIED_BLOCK: &str = "[00:01.000]Hello [00:02.000]world[00:03.000]\n[00:04.000]again[00:05.000]";
+
+    #[test]
+    fn pastes_timing_onto_same_text() {
+        let lyrics = format!("{}\n\nHello [00:50.000]world\nagain\n\nbye", COPIED_BLOCK);
+        assert_eq!(paste_block_timing(&lyrics, COPIED_BLOCK, 3..5, secs(10.)).unwrap(), format!(
+            "{}\n\n[00:10.000]Hello [00:11.000]world[00:12.000]\n[00:13.000]again[00:14.000]\n\nbye", COPIED_BLOCK));
+    }
+
+    #[test]
+    fn pastes_timing_in_order_onto_other_text() {
+        let lyrics = "[00:20.000]Good[00:20.500]bye [00:21.000]you\n[00:22.000]all[00:23.000]";
+        assert_eq!(paste_block_timing(lyrics, COPIED_BLOCK, 0..2, secs(10.)).unwrap(),
+            "[00:10.000]Good[00:11.000]bye [00:12.000]you\n[00:13.000]all[00:14.000]");
+    }
+
+    #[test]
+    fn pasting_needs_same_text_or_timestamp_count() {
+        let lyrics = "[00:20.000]Goodbye[00:22.000]";
+        assert_eq!(paste_block_timing(lyrics, COPIED_BLOCK, 0..1, secs(10.)),
+            Err("The blocks need the same text or the same number of timestamps".to_string()));
+        // lines that aren't there anymore
+        assert!(paste_block_timing(lyrics, COPIED_BLOCK, 1..3, secs(10.)).is_err());
+        assert!(paste_block_timing(lyrics, "no timestamps", 0..1, secs(10.)).is_err());
+    }
+
+    #[test]
+    fn pasting_keeps_syllable_ends() {
+        let copied = "[00:01.000]Hold[00:02.000>] on[00:03.000]";
+        assert_eq!(paste_block_timing("Hold on", copied, 0..1, secs(10.)).unwrap(),
+            "[00:10.000]Hold[00:11.000>] on[00:12.000]");
+        // copied onto other text, the syllable end is copied in its place
+        assert_eq!(paste_block_timing("[00:40.000]Wait[00:41.000] up[00:42.000]", copied, 0..1, secs(10.)).unwrap(),
+            "[00:10.000]Wait[00:11.000>] up[00:12.000]");
+    }
+
+    #[test]
+    fn pasted_timing_starts_at_start_time() {
+        let copied = "[00:05.000]Hello [00:06.500]world[00:08.000]";
+        assert_eq!(paste_block_timing("Hello world", copied, 0..1, secs(2.)).unwrap(),
+            "[00:02.000]Hello [00:03.500]world[00:05.000]");
+        assert_eq!(paste_block_timing("Hello world", copied, 0..1, secs(5.)).unwrap(), copied);
+    }
+}
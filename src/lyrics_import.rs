@@ -0,0 +1,319 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiClipboard, EguiContexts};
+use bevy_file_dialog::prelude::*;
+use regex::Regex;
+
+use crate::editor::{show_and_log_info, EditorState};
+use crate::lyrics::{get_displayed_text, TIMECODE_PATTERN};
+
+pub struct LyricsImportPlugin;
+
+impl Plugin for LyricsImportPlugin {
+  fn build(&self, app: &mut App) {
+    app.insert_resource(LyricsImportDialog::default());
+    app.add_systems(Update, handle_lyrics_text_file_dialog);
+  }
+}
+
+pub struct LyricsTextFileDialog;
+
+pub fn configure_file_dialog_plugin(plugin: FileDialogPlugin) -> FileDialogPlugin {
+  plugin.with_load_file::<LyricsTextFileDialog>()
+}
+
+/// What to do with section headers like `[Chorus]`.
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum SectionLabels {
+  #[default]
+  Remove,
+  /// Keeps them as `[section=...]` tags at the start of the section.
+  Keep,
+}
+
+#[derive(Clone)]
+pub struct CleanupOptions {
+  pub section_labels: SectionLabels,
+  /// In characters. 0 doesn't split lines.
+  pub max_line_length: usize,
+}
+
+impl Default for CleanupOptions {
+  fn default() -> Self {
+    Self {
+      section_labels: SectionLabels::Remove,
+      max_line_length: 0,
+    }
+  }
+}
+
+#[derive(Resource, Default)]
+pub struct LyricsImportDialog {
+  is_open: bool,
+  text: String,
+  options: CleanupOptions,
+  /// Whether "Replace Lyrics" is waiting to be confirmed.
+  is_confirming_replace: bool,
+}
+
+impl LyricsImportDialog {
+  pub fn open(&mut self) {
+    self.is_open = true;
+  }
+}
+
+/// Collapses whitespace and blank lines, and starts a block at each section header.
+pub fn clean_up_lyrics(text: &str, options: &CleanupOptions) -> String {
+  let section_regex = Regex::new(r"^\[([^\]=]+)\]$").unwrap();
+  let timecode_regex = Regex::new(TIMECODE_PATTERN).unwrap();
+
+  let normalized = String::from_iter(normalize_line_endings::normalized(text.chars()));
+  let mut lines: Vec<String> = Vec::new();
+  let mut pending_label = None;
+  for line in normalized.split('\n') {
+    let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    if let Some(captures) = section_regex.captures(&line).filter(|_| !timecode_regex.is_match(&line)) {
+      if lines.last().is_some_and(|last| !last.is_empty()) {
+        lines.push(String::new());
+      }
+      if options.section_labels == SectionLabels::Keep {
+        pending_label = Some(captures[1].trim().to_string());
+      }
+      continue;
+    }
+
+    if line.is_empty() {
+      if lines.last().is_some_and(|last| !last.is_empty()) {
+        lines.push(String::new());
+      }
+      continue;
+    }
+
+    for (idx, part) in split_long_line(&line, options.max_line_length).into_iter().enumerate() {
+      match pending_label.take().filter(|_| idx == 0) {
+        Some(label) => lines.push(format!("[section={}]{}", label, part)),
+        None => lines.push(part),
+      }
+    }
+  }
+
+  while lines.last().is_some_and(|last| last.is_empty()) {
+    lines.pop();
+  }
+  lines.join("\n")
+}
+
+/// Splits a line between words into parts of at most `max_length` displayed characters.
+fn split_long_line(line: &str, max_length: usize) -> Vec<String> {
+  if max_length == 0 || get_displayed_text(line).chars().count() <= max_length {
+    return vec![line.to_string()];
+  }
+
+  let mut parts: Vec<String> = Vec::new();
+  let mut curr_part = String::new();
+  let mut curr_length = 0;
+  for word in split_words(line) {
+    let word_length = get_displayed_text(word).chars().count();
+    if !curr_part.is_empty() && curr_length + 1 + word_length > max_length {
+      parts.push(std::mem::take(&mut curr_part));
+      curr_length = 0;
+    }
+    if !curr_part.is_empty() {
+      curr_part.push(' ');
+      curr_length += 1;
+    }
+    curr_part.push_str(word);
+    curr_length += word_length;
+  }
+  if !curr_part.is_empty() {
+    parts.push(curr_part);
+  }
+  parts
+}
+
+/// Splits a line at spaces outside tags and ruby.
+fn split_words(line: &str) -> Vec<&str> {
+  let mut words = Vec::new();
+  let mut word_start = 0;
+  let mut in_tag = false;
+  let mut in_ruby = false;
+  for (idx, c) in line.char_indices() {
+    match c {
+      '[' => in_tag = true,
+      ']' => in_tag = false,
+      '{' => in_ruby = true,
+      '}' => in_ruby = false,
+      ' ' if !in_tag && !in_ruby => {
+        words.push(&line[word_start..idx]);
+        word_start = idx + 1;
+      },
+      _ => {}
+    }
+  }
+  words.push(&line[word_start..]);
+  words
+}
+
+fn handle_lyrics_text_file_dialog(mut events: EventReader<DialogFileLoaded<LyricsTextFileDialog>>,
+  mut lyrics_import_dialog: ResMut<LyricsImportDialog>
+) {
+  for ev in events.read() {
+    lyrics_import_dialog.text = String::from_utf8_lossy(&ev.contents).into_owned();
+    lyrics_import_dialog.is_open = true;
+  }
+}
+
+pub fn lyrics_import_dialog_ui(mut egui_contexts: EguiContexts,
+  mut lyrics_import_dialog: ResMut<LyricsImportDialog>,
+  mut editor_state: NonSendMut<EditorState>,
+  mut clipboard: ResMut<EguiClipboard>,
+  mut commands: Commands
+) {
+  if !lyrics_import_dialog.is_open {
+    return;
+  }
+
+  let mut is_open = true;
+  let mut replace_desired = false;
+  let mut append_desired = false;
+  let dialog = lyrics_import_dialog.as_mut();
+  egui::Window::new("Import Lyrics").open(&mut is_open).show(egui_contexts.ctx_mut(), |ui| {
+    ui.horizontal(|ui| {
+      if ui.button("Open Text File...").clicked() {
+        commands.dialog().add_filter("Text file", &["txt"]).load_file::<LyricsTextFileDialog>();
+      }
+      if ui.button("Paste from Clipboard").clicked() {
+        if let Some(contents) = clipboard.get_contents() {
+          dialog.text = contents;
+        }
+      }
+    });
+    egui::ScrollArea::vertical().id_salt("lyrics_import_text").max_height(200.).show(ui, |ui| {
+      ui.add(egui::TextEdit::multiline(&mut dialog.text)
+        .hint_text("Paste lyrics here")
+        .desired_width(f32::INFINITY));
+    });
+
+    ui.separator();
+    ui.horizontal(|ui| {
+      ui.label("Section Labels");
+      ui.radio_value(&mut dialog.options.section_labels, SectionLabels::Remove, "Remove");
+      ui.radio_value(&mut dialog.options.section_labels, SectionLabels::Keep, "Keep as Tags");
+    });
+    ui.horizontal(|ui| {
+      let mut split_lines = dialog.options.max_line_length > 0;
+      if ui.checkbox(&mut split_lines, "Split Lines Longer Than").changed() {
+        dialog.options.max_line_length = if split_lines { 40 } else { 0 };
+      }
+      ui.add_enabled(split_lines, egui::DragValue::new(&mut dialog.options.max_line_length)
+        .range(1..=200)
+        .suffix(" characters"));
+    });
+
+    ui.separator();
+    ui.label("Preview");
+    let cleaned = clean_up_lyrics(&dialog.text, &dialog.options);
+    egui::ScrollArea::vertical().id_salt("lyrics_import_preview").max_height(200.).show(ui, |ui| {
+      ui.add(egui::Label::new(egui::RichText::new(&cleaned).monospace()));
+    });
+
+    ui.separator();
+    let can_import = !cleaned.is_empty() && editor_state.project_data.is_some();
+    ui.horizontal(|ui| {
+      if ui.add_enabled(can_import, egui::Button::new("Replace Lyrics")).clicked() {
+        // replacing loses the lyrics' timing, so check first
+        let has_lyrics = editor_state.project_data.as_ref()
+          .is_some_and(|project_data| !project_data.lyrics.trim().is_empty());
+        if has_lyrics {
+          dialog.is_confirming_replace = true;
+        } else {
+          replace_desired = true;
+        }
+      }
+      if ui.add_enabled(can_import, egui::Button::new("Append to Lyrics")).clicked() {
+        append_desired = true;
+      }
+    });
+  });
+
+  if dialog.is_confirming_replace && is_open {
+    egui::Window::new("Replace Lyrics?").collapsible(false).show(egui_contexts.ctx_mut(), |ui| {
+      ui.label("This replaces the project's lyrics and all their timestamps. Are you sure?");
+      ui.horizontal(|ui| {
+        if ui.button("Cancel").clicked() {
+          dialog.is_confirming_replace = false;
+        }
+        if ui.button("Replace Lyrics").clicked() {
+          dialog.is_confirming_replace = false;
+          replace_desired = true;
+        }
+      });
+    });
+  }
+
+  if replace_desired || append_desired {
+    let cleaned = clean_up_lyrics(&dialog.text, &dialog.options);
+    if let Some(project_data) = editor_state.project_data.as_mut() {
+      if append_desired && !project_data.lyrics.trim().is_empty() {
+        project_data.lyrics = format!("{}\n\n{}", project_data.lyrics.trim_end(), cleaned);
+      } else {
+        project_data.lyrics = cleaned;
+      }
+    }
+    editor_state.lyrics_dirty = true;
+    editor_state.needs_save_before_exit = true;
+    show_and_log_info(editor_state.as_mut(), "Imported lyrics".into());
+    is_open = false;
+  }
+  if !is_open {
+    dialog.is_confirming_replace = false;
+  }
+  dialog.is_open = is_open;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn options(section_labels: SectionLabels, max_line_length: usize) -> CleanupOptions {
+    CleanupOptions {
+      section_labels,
+      max_line_length,
+    }
+  }
+
+  #[test]
+  fn collapses_whitespace_and_blank_lines() {
+    let text = "\r\n  Hello   there \r\nworld\r\n\r\n\r\n \r\nsecond   verse\n\n\n";
+    assert_eq!(clean_up_lyrics(text, &CleanupOptions::default()), "Hello there\nworld\n\nsecond verse");
+  }
+
+  #[test]
+  fn section_labels_start_blocks() {
+    let text = "[Verse 1]\nline one\n[Chorus]\nla la\n\n\n[Verse 2]\n\nline two";
+    assert_eq!(clean_up_lyrics(text, &options(SectionLabels::Remove, 0)), "line one\n\nla la\n\nline two");
+    assert_eq!(clean_up_lyrics(text, &options(SectionLabels::Keep, 0)),
+      "[section=Verse 1]line one\n\n[section=Chorus]la la\n\n[section=Verse 2]line two");
+  }
+
+  #[test]
+  fn timestamps_are_not_section_labels() {
+    let text = "[00:01.000]\nhello";
+    assert_eq!(clean_up_lyrics(text, &CleanupOptions::default()), "[00:01.000]\nhello");
+  }
+
+  #[test]
+  fn splits_long_lines_between_words() {
+    assert_eq!(split_long_line("one two three four", 9), vec!["one two", "three", "four"]);
+    assert_eq!(split_long_line("one two", 0), vec!["one two"]);
+    assert_eq!(split_long_line("supercalifragilistic is", 9), vec!["supercalifragilistic", "is"]);
+  }
+
+  #[test]
+  fn split_ignores_tags_and_ruby() {
+    // shown as "one two three", which fits
+    let line = "[color=#ff0000]one [singer=1 2]two {three|th ree}";
+    assert_eq!(split_long_line(line, 13), vec![line]);
+    assert_eq!(split_long_line(line, 9), vec!["[color=#ff0000]one [singer=1 2]two", "{three|th ree}"]);
+  }
+}
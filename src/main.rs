@@ -33,6 +33,27 @@ use crate::stage::StagePlugin;
 mod audio;
 use crate::audio::AudioPlugin;
 
+mod vocal_removal;
+use crate::vocal_removal::VocalRemovalPlugin;
+
+mod onsets;
+use crate::onsets::OnsetPlugin;
+
+mod beats;
+use crate::beats::BeatsPlugin;
+
+mod alignment;
+use crate::alignment::AlignmentPlugin;
+
+mod search;
+use crate::search::SearchPlugin;
+
+mod syllables;
+use crate::syllables::SyllablesPlugin;
+
+mod lyrics_import;
+use crate::lyrics_import::LyricsImportPlugin;
+
 mod editor;
 use crate::editor::EditorPlugin;
 
@@ -44,6 +65,12 @@ use timeline::TimelinePlugin;
 mod help;
 use help::HelpPlugin;
 
+mod background;
+use background::BackgroundPlugin;
+
+mod theme;
+use theme::ThemePlugin;
+
 use bevy_tokio_tasks::TokioTasksPlugin;
 
 fn main() {
@@ -82,8 +109,16 @@ fn main() {
     .add_plugins(EguiPlugin)
     .add_plugins(TokioTasksPlugin::default())
     .add_plugins(
-      export::configure_file_dialog_plugin(
-        project::configure_file_dialog_plugin(FileDialogPlugin::new())
+      lyrics_import::configure_file_dialog_plugin(
+        alignment::configure_file_dialog_plugin(
+          theme::configure_file_dialog_plugin(
+            background::configure_file_dialog_plugin(
+              export::configure_file_dialog_plugin(
+                project::configure_file_dialog_plugin(FileDialogPlugin::new())
+              )
+            )
+          )
+        )
       )
     )
     .add_plugins(EditorPlugin)
@@ -93,6 +128,15 @@ fn main() {
     .add_plugins(StagePlugin)
     .add_plugins(TimelinePlugin)
     .add_plugins(HelpPlugin)
+    .add_plugins(BackgroundPlugin)
+    .add_plugins(ThemePlugin)
+    .add_plugins(VocalRemovalPlugin)
+    .add_plugins(OnsetPlugin)
+    .add_plugins(BeatsPlugin)
+    .add_plugins(AlignmentPlugin)
+    .add_plugins(SearchPlugin)
+    .add_plugins(SyllablesPlugin)
+    .add_plugins(LyricsImportPlugin)
     .add_plugins(DefaultInspectorConfigPlugin);
 
 
@@ -0,0 +1,418 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_tokio_tasks::TokioTasksRuntime;
+use kira::sound::static_sound::StaticSoundData;
+use regex::Regex;
+
+use crate::audio::Biquad;
+use crate::editor::{show_and_log_error, show_and_log_info, EditorState};
+use crate::lyrics::{
+  format_syllable_end_timecode, format_timecode, get_source_words, get_timecode_time,
+  is_syllable_end_timecode, retime_timecodes, Block, SYLLABLE_BREAK, TIMECODE_PATTERN
+};
+use crate::syllables::get_syllable_breaks;
+
+pub struct OnsetPlugin;
+
+impl Plugin for OnsetPlugin {
+  fn build(&self, app: &mut App) {
+    app.add_event::<DetectOnsetsRequestedEvent>();
+    app.add_systems(Update, handle_detect_onsets_requested);
+    app.insert_resource(OnsetState::default());
+  }
+}
+
+// samples per analysis frame, about 12ms at 44.1kHz
+const HOP_SIZE: usize = 512;
+// how far either side of a frame to look when deciding if it's a peak
+const PEAK_WINDOW: usize = 3;
+// how far either side of a frame to average over for the threshold
+const THRESHOLD_WINDOW: usize = 16;
+// how far above the local average a rise in log energy has to be
+const ONSET_THRESHOLD: f32 = 0.7;
+// frames quieter than this relative to the loudest one are ignored
+const SILENCE_THRESHOLD: f32 = 1e-4;
+// onsets closer together than this are treated as one, in seconds
+const MIN_ONSET_GAP: f32 = 0.08;
+/// How far a timestamp can be from an onset to be snapped to it, in seconds.
+pub const SNAP_TOLERANCE: f32 = 0.15;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Onset {
+  /// Song time, in seconds.
+  pub time: f32,
+  /// How sharp the rise in energy is.
+  pub strength: f32,
+}
+
+#[derive(Resource, Default)]
+pub struct OnsetState {
+  pub onsets: Vec<Onset>,
+  /// The audio file the onsets were found in.
+  pub analyzed_file: Option<PathBuf>,
+  is_analyzing: bool,
+}
+
+impl OnsetState {
+  pub fn is_analyzing(&self) -> bool {
+    self.is_analyzing
+  }
+
+  /// Finds the onset closest to `time`, if there's one within `tolerance`.
+  pub fn get_nearest_onset(&self, time: f32, tolerance: f32) -> Option<&Onset> {
+    self.onsets.iter()
+      .filter(|onset| (onset.time - time).abs() <= tolerance)
+      .min_by(|a, b| (a.time - time).abs().total_cmp(&(b.time - time).abs()))
+  }
+}
+
+#[derive(Event, Default)]
+pub struct DetectOnsetsRequestedEvent;
+
+fn handle_detect_onsets_requested(
+  mut events: EventReader<DetectOnsetsRequestedEvent>,
+  mut editor_state: NonSendMut<EditorState>,
+  mut onset_state: ResMut<OnsetState>,
+  tokio_runtime: Res<TokioTasksRuntime>
+) {
+  for _ in events.read() {
+    if onset_state.is_analyzing {
+      continue;
+    }
+
+    // the guide vocal has the clearest onsets, if there is one
+    let Some(audio_file) = editor_state.project_data.as_ref()
+      .and_then(|project_data| project_data.vocal_file.clone().or(project_data.song_file.clone())) else
+    {
+      show_and_log_error(editor_state.as_mut(), "Can't detect onsets without a song file".into());
+      continue;
+    };
+
+    onset_state.is_analyzing = true;
+
+    tokio_runtime.spawn_background_task(|mut ctx| async move {
+      let result = StaticSoundData::from_file(&audio_file)
+        .map(|sound_data| detect_onsets(&sound_data))
+        .map_err(|e| format!("{:?}", e));
+
+      ctx.run_on_main_thread(move |ctx| {
+        let onset_count = {
+          let mut onset_state = ctx.world.resource_mut::<OnsetState>();
+          onset_state.is_analyzing = false;
+          if let Ok(onsets) = &result {
+            onset_state.onsets = onsets.clone();
+            onset_state.analyzed_file = Some(audio_file.clone());
+          }
+          onset_state.onsets.len()
+        };
+
+        let mut editor_state = ctx.world.get_non_send_resource_mut::<EditorState>().unwrap();
+        match result {
+          Ok(_) => show_and_log_info(editor_state.as_mut(),
+            format!("Found {} onsets in {:?}", onset_count, audio_file)),
+          Err(e) => show_and_log_error(editor_state.as_mut(),
+            format!("Error detecting onsets in {:?}: {}", audio_file, e)),
+        }
+      }).await;
+    });
+  }
+}
+
+/// How sharply the energy of a band of the audio rises, per analysis frame.
+pub struct OnsetEnvelope {
+  pub energies: Vec<f32>,
+  pub flux: Vec<f32>,
+  /// Seconds per analysis frame.
+  pub hop_time: f32,
+}
+
+/// Gets the onset envelope of the audio filtered to a band around `center` Hz.
+pub fn get_onset_envelope(sound_data: &StaticSoundData, center: f32, q: f32) -> OnsetEnvelope {
+  let sample_rate = sound_data.sample_rate as f32;
+  let mut band = Biquad::band_pass(center, q, sample_rate);
+  let energies = sound_data.frames.chunks(HOP_SIZE)
+    .map(|chunk| {
+      chunk.iter()
+        .map(|frame| band.process((frame.left + frame.right) / 2.).powi(2))
+        .sum::<f32>() / chunk.len() as f32
+    })
+    .collect::<Vec<_>>();
+
+  let flux = energies.iter()
+    .scan(None, |prev_log_energy: &mut Option<f32>, energy| {
+      let log_energy = (energy + f32::EPSILON).ln();
+      let rise = prev_log_energy.map(|prev| (log_energy - prev).max(0.)).unwrap_or(0.);
+      *prev_log_energy = Some(log_energy);
+      Some(rise)
+    })
+    .collect::<Vec<_>>();
+
+  OnsetEnvelope {
+    energies,
+    flux,
+    hop_time: HOP_SIZE as f32 / sample_rate,
+  }
+}
+
+fn detect_onsets(sound_data: &StaticSoundData) -> Vec<Onset> {
+  // the range most vocals sit in
+  let OnsetEnvelope { energies, flux, hop_time } = get_onset_envelope(sound_data, 1000., 0.7);
+  let max_energy = energies.iter().cloned().fold(0., f32::max);
+
+  let mut onsets: Vec<Onset> = Vec::new();
+  for (idx, rise) in flux.iter().enumerate() {
+    if energies[idx] < max_energy * SILENCE_THRESHOLD {
+      continue;
+    }
+
+    let peak_range = idx.saturating_sub(PEAK_WINDOW)..(idx + PEAK_WINDOW + 1).min(flux.len());
+    if flux[peak_range].iter().any(|other| other > rise) {
+      continue;
+    }
+
+    let threshold_range = idx.saturating_sub(THRESHOLD_WINDOW)..(idx + THRESHOLD_WINDOW + 1).min(flux.len());
+    let local_mean = flux[threshold_range.clone()].iter().sum::<f32>() / threshold_range.len() as f32;
+    if *rise < local_mean + ONSET_THRESHOLD {
+      continue;
+    }
+
+    let onset = Onset {
+      time: idx as f32 * hop_time,
+      strength: *rise,
+    };
+    match onsets.last_mut() {
+      Some(last) if onset.time - last.time < MIN_ONSET_GAP => {
+        if onset.strength > last.strength {
+          *last = onset;
+        }
+      },
+      _ => onsets.push(onset),
+    }
+  }
+
+  onsets
+}
+
+/// Moves every timestamp to the nearest onset within `SNAP_TOLERANCE`. Returns the new lyrics
+/// and how many moved.
+pub fn snap_timestamps_to_onsets(lyrics: &str, onset_state: &OnsetState) -> (String, usize) {
+  retime_timecodes(lyrics, |time| {
+    onset_state.get_nearest_onset(time.as_secs_f32(), SNAP_TOLERANCE)
+      .map(|onset| Duration::from_secs_f32(onset.time))
+  })
+}
+
+/// Re-times a block with a timestamp at each syllable start, placed on the strongest onsets.
+/// Returns the new lyrics.
+pub fn distribute_block_over_onsets(lyrics: &str, block: &Block, onset_state: &OnsetState)
+  -> Result<String, String>
+{
+  let (Some(first_timestamp), Some(last_timestamp)) = (block.timestamps.first(), block.timestamps.last()) else {
+    return Err("The block needs timestamps at its start and end".into());
+  };
+  let (start_time, end_time) = (first_timestamp.time, last_timestamp.time);
+  if end_time <= start_time {
+    return Err("The block needs timestamps at its start and end".into());
+  }
+
+  let timecode_regex = Regex::new(TIMECODE_PATTERN).unwrap();
+  let mut lines = lyrics.split('\n').map(String::from).collect::<Vec<_>>();
+  let Some(block_lines) = lines.get_mut(block.line_range.clone()) else {
+    return Err("The block is out of date with the lyrics".into());
+  };
+
+  // syllable ends with their byte range in the line once the other
+  // timestamps are taken out
+  let mut syllable_ends = Vec::new();
+  for (line_idx, line) in block_lines.iter_mut().enumerate() {
+    let mut new_line = String::with_capacity(line.len());
+    let mut copied_up_to = 0;
+    for captures in timecode_regex.captures_iter(line) {
+      let timecode = captures.get(0).unwrap();
+      new_line.push_str(&line[copied_up_to..timecode.start()]);
+      copied_up_to = timecode.end();
+      if is_syllable_end_timecode(&captures) {
+        syllable_ends.push((line_idx, new_line.len()..(new_line.len() + timecode.len()), get_timecode_time(&captures)));
+        new_line.push_str(timecode.as_str());
+      } else if new_line.ends_with(|c: char| !c.is_whitespace())
+        && line[timecode.end()..].starts_with(|c: char| !c.is_whitespace() && c != '[')
+      {
+        new_line.push(SYLLABLE_BREAK);
+      }
+    }
+    new_line.push_str(&line[copied_up_to..]);
+    *line = new_line;
+  }
+
+  // the byte range each syllable's timestamp replaces: its syllable break, or
+  // an empty range at the start of a word
+  let syllable_starts = block_lines.iter()
+    .enumerate()
+    .flat_map(|(line_idx, line)| {
+      let breaks = get_syllable_breaks(line);
+      let mut starts = get_source_words(line).into_iter()
+        .map(|word| word.start..word.start)
+        .filter(|start| !breaks.contains(&start.start))
+        .chain(breaks.iter().map(|position| *position..(*position + SYLLABLE_BREAK.len_utf8())))
+        .collect::<Vec<_>>();
+      starts.sort_by_key(|start| start.start);
+      starts.into_iter().map(move |start| (line_idx, start))
+    })
+    .collect::<Vec<_>>();
+  if syllable_starts.is_empty() {
+    return Err("The block has no words".into());
+  }
+
+  let mut times = vec![start_time];
+  times.extend(choose_onset_times(onset_state, start_time.as_secs_f32(), end_time.as_secs_f32(),
+    syllable_starts.len() - 1).into_iter().map(Duration::from_secs_f32));
+
+  let mut replacements = syllable_starts.iter()
+    .zip(&times)
+    .map(|((line_idx, range), time)| (*line_idx, range.clone(), format_timecode(time)))
+    .collect::<Vec<_>>();
+  for (line_idx, range, time) in syllable_ends {
+    let next_idx = syllable_starts.partition_point(|(start_line_idx, start)| {
+      (*start_line_idx, start.start) < (line_idx, range.start)
+    });
+    let min_time = times[..next_idx].last().copied().unwrap_or(start_time);
+    let max_time = times.get(next_idx).copied().unwrap_or(end_time);
+    replacements.push((line_idx, range, format_syllable_end_timecode(&time.max(min_time).min(max_time))));
+  }
+  if !last_timestamp.is_syllable_end {
+    if let Some(last_line) = block_lines.last_mut() {
+      last_line.push_str(&format_timecode(&end_time));
+    }
+  }
+
+  // replace from the end so earlier positions stay valid
+  replacements.sort_by_key(|(line_idx, range, _)| (*line_idx, range.start));
+  for (line_idx, range, timecode) in replacements.into_iter().rev() {
+    block_lines[line_idx].replace_range(range, &timecode);
+  }
+
+  Ok(lines.join("\n"))
+}
+
+/// Picks `count` times from the strongest onsets, splitting the longest gaps if there aren't enough.
+fn choose_onset_times(onset_state: &OnsetState, start_time: f32, end_time: f32, count: usize) -> Vec<f32> {
+  let mut candidates = onset_state.onsets.iter()
+    .filter(|onset| onset.time > start_time + MIN_ONSET_GAP && onset.time < end_time - MIN_ONSET_GAP)
+    .collect::<Vec<_>>();
+  candidates.sort_by(|a, b| b.strength.total_cmp(&a.strength));
+  let mut times = candidates.into_iter()
+    .take(count)
+    .map(|onset| onset.time)
+    .collect::<Vec<_>>();
+  times.sort_by(f32::total_cmp);
+
+  while times.len() < count {
+    let mut bounds = vec![start_time];
+    bounds.extend(times.iter().cloned());
+    bounds.push(end_time);
+    let (gap_idx, _) = bounds.windows(2)
+      .enumerate()
+      .max_by(|(_, a), (_, b)| (a[1] - a[0]).total_cmp(&(b[1] - b[0])))
+      .unwrap();
+    times.insert(gap_idx, (bounds[gap_idx] + bounds[gap_idx + 1]) / 2.);
+  }
+
+  times
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use kira::sound::static_sound::StaticSoundSettings;
+  use kira::Frame;
+
+  use super::*;
+  use crate::lyrics::ParsedLyrics;
+
+  // onsets from (time, strength) pairs
+  fn onset_state(onsets: &[(f32, f32)]) -> OnsetState {
+    OnsetState {
+      onsets: onsets.iter().map(|(time, strength)| Onset { time: *time, strength: *strength }).collect(),
+      ..default()
+    }
+  }
+
+  fn distribute(lyrics: &str, onset_state: &OnsetState) -> Result<String, String> {
+    let parsed_lyrics = ParsedLyrics::parse(&lyrics.to_string()).unwrap();
+    distribute_block_over_onsets(lyrics, &parsed_lyrics.blocks[0], onset_state)
+  }
+
+  #[test]
+  fn chooses_strongest_onsets_in_order() {
+    // the first and last are too close to the ends of the range
+    let onset_state = onset_state(&[(0.05, 9.), (1., 1.), (1.5, 3.), (2., 2.), (2.95, 9.)]);
+    assert_eq!(choose_onset_times(&onset_state, 0., 3., 2), vec![1.5, 2.]);
+  }
+
+  #[test]
+  fn splits_longest_gap_without_enough_onsets() {
+    assert_eq!(choose_onset_times(&onset_state(&[(1., 1.)]), 0., 4., 2), vec![1., 2.5]);
+    assert_eq!(choose_onset_times(&onset_state(&[]), 0., 4., 1), vec![2.]);
+  }
+
+  #[test]
+  fn snaps_timestamps_within_tolerance() {
+    let onset_state = onset_state(&[(1., 1.), (1.75, 1.), (3.125, 1.)]);
+    let (lyrics, snapped_count) = snap_timestamps_to_onsets("[00:01.050]Hel[00:01.500]lo[00:03.000>]", &onset_state);
+    assert_eq!(lyrics, "[00:01.000]Hel[00:01.500]lo[00:03.125>]");
+    assert_eq!(snapped_count, 2);
+  }
+
+  #[test]
+  fn detects_tone_bursts() {
+    const SAMPLE_RATE: u32 = 44100;
+    let burst_starts = [0.5, 1., 1.5];
+    let frames = (0..(SAMPLE_RATE * 2))
+      .map(|idx| {
+        let t = idx as f32 / SAMPLE_RATE as f32;
+        let is_sounding = burst_starts.iter().any(|start| (*start..(start + 0.2)).contains(&t));
+        let sample = if is_sounding { (t * 1000. * std::f32::consts::TAU).sin() * 0.5 } else { 0. };
+        Frame { left: sample, right: sample }
+      })
+      .collect::<Arc<[Frame]>>();
+    let sound_data = StaticSoundData {
+      sample_rate: SAMPLE_RATE,
+      frames,
+      settings: StaticSoundSettings::default(),
+      slice: None,
+    };
+
+    let onsets = detect_onsets(&sound_data);
+    assert_eq!(onsets.len(), burst_starts.len());
+    for (onset, start) in onsets.iter().zip(burst_starts) {
+      assert!((onset.time - start).abs() < 0.03, "onset at {} for burst at {}", onset.time, start);
+    }
+  }
+
+  #[test]
+  fn distributes_over_syllables() {
+    let onset_state = onset_state(&[(2., 1.), (3., 1.)]);
+    assert_eq!(distribute("[00:01.000]syl|la|ble [00:04.000]", &onset_state).unwrap(),
+      "[00:01.000]syl[00:02.000]la[00:03.000]ble [00:04.000]");
+  }
+
+  #[test]
+  fn keeps_syllable_ends() {
+    let lyrics = "[00:01.000]Hel[00:01.250]lo[00:02.000>]\n[00:03.500]world[00:04.000>]";
+    assert_eq!(distribute(lyrics, &onset_state(&[(1.5, 1.), (3., 2.)])).unwrap(),
+      "[00:01.000]Hel[00:01.500]lo[00:02.000>]\n[00:03.000]world[00:04.000>]");
+    // an end before its syllable now starts moves up to it
+    assert_eq!(distribute(lyrics, &onset_state(&[(2.5, 1.), (3., 2.)])).unwrap(),
+      "[00:01.000]Hel[00:02.500]lo[00:02.500>]\n[00:03.000]world[00:04.000>]");
+  }
+
+  #[test]
+  fn leaves_other_blocks_alone() {
+    let lyrics = "[00:01.000]one two[00:02.000]\n\n[00:05.000]three";
+    assert_eq!(distribute(lyrics, &onset_state(&[(1.5, 1.), (5.5, 1.)])).unwrap(),
+      "[00:01.000]one [00:01.500]two[00:02.000]\n\n[00:05.000]three");
+  }
+}
@@ -16,10 +16,16 @@ use bevy::render::render_resource::{AsBindGroupShaderType, Extent3d, TextureDime
 use bevy_egui::egui::load::SizedTexture;
 use bevy_file_dialog::prelude::*;
 use bevy::ecs::world::CommandQueue;
-use kira::Tween;
 
 use crate::editor::{AudioState, EditorState, show_and_log_error, show_and_log_info};
-use crate::stage::TitlecardUpdatedEvent;
+use crate::stage::{CountdownSettings, LyricLayout, LyricStyle, SingerSettings, TextFitSettings, TitlecardSettings, TitlecardUpdatedEvent, WritingSettings};
+use crate::background::{BackgroundDialog, BackgroundSettings};
+use crate::theme::ThemeDialog;
+use crate::audio::GuideVocalSettings;
+use crate::beats::BeatGrid;
+use crate::syllables::SyllabificationSettings;
+use crate::lyrics_import::LyricsImportDialog;
+use crate::vocal_removal::{GenerateInstrumentalRequestedEvent, VocalRemovalSettings, VocalRemovalState};
 
 pub struct ProjectPlugin;
 
@@ -32,7 +38,9 @@ impl Plugin for ProjectPlugin {
     app.add_systems(Update, handle_save_project_requested_event);
     app.add_systems(Update, handle_open_project_dialog);
     app.add_systems(Update, handle_thumbnail_file_path_dialog);
+    app.add_systems(Update, handle_font_file_path_dialog);
     app.add_systems(Update, handle_song_file_path_dialog);
+    app.add_systems(Update, handle_vocal_file_path_dialog);
     app.add_event::<NewProjectRequestedEvent>();
     app.add_event::<NewProjectDialogSubmittedEvent>();
     app.add_event::<SaveProjectRequestedEvent>();
@@ -43,6 +51,10 @@ impl Plugin for ProjectPlugin {
     app.insert_resource(ProjectSettingsDialog::default());
     app.register_type::<SongFilePath>();
     app.register_type_data::<SongFilePath, InspectorEguiImpl>();
+    app.register_type::<VocalFilePath>();
+    app.register_type_data::<VocalFilePath, InspectorEguiImpl>();
+    app.register_type::<FontFilePath>();
+    app.register_type_data::<FontFilePath, InspectorEguiImpl>();
   }
 }
 
@@ -208,12 +220,28 @@ pub struct ProjectData {
   pub artist: String,
   pub title: String,
   pub song_file: Option<PathBuf>,
+  /// Guide vocal to sync against. `song_file` is the instrumental.
+  pub vocal_file: Option<PathBuf>,
   pub background_color: Option<Color>,
   pub unsung_color: Option<Color>,
   pub sung_color: Option<Color>,
   pub thumbnail_path: Option<PathBuf>,
   pub titlecard_show_time: Option<f32>,
   pub song_delay_time: Option<f32>,
+  pub layout: Option<LyricLayout>,
+  pub font_path: Option<PathBuf>,
+  pub style: Option<LyricStyle>,
+  pub background: Option<BackgroundSettings>,
+  pub titlecard: Option<TitlecardSettings>,
+  pub singers: Option<SingerSettings>,
+  pub countdown: Option<CountdownSettings>,
+  pub writing: Option<WritingSettings>,
+  pub guide_vocal: Option<GuideVocalSettings>,
+  pub instrumental_file: Option<PathBuf>,
+  pub vocal_removal: Option<VocalRemovalSettings>,
+  pub beat_grid: Option<BeatGrid>,
+  pub syllabification: Option<SyllabificationSettings>,
+  pub text_fit: Option<TextFitSettings>,
 }
 
 impl Default for ProjectData {
@@ -223,12 +251,27 @@ impl Default for ProjectData {
       artist: default(),
       title: default(),
       song_file: None,
+      vocal_file: None,
       background_color: Some(Color::BLACK),
       sung_color: Some(Color::WHITE),
       unsung_color: Some(Color::srgb(0.5, 0.5, 0.5)),
       thumbnail_path: None,
       titlecard_show_time: Some(10.),
-      song_delay_time: Some(0.)
+      song_delay_time: Some(0.),
+      layout: Some(LyricLayout::default()),
+      font_path: None,
+      style: Some(LyricStyle::default()),
+      background: None,
+      titlecard: Some(TitlecardSettings::default()),
+      singers: Some(SingerSettings::default()),
+      countdown: Some(CountdownSettings::default()),
+      writing: Some(WritingSettings::default()),
+      guide_vocal: Some(GuideVocalSettings::default()),
+      instrumental_file: None,
+      vocal_removal: Some(VocalRemovalSettings::default()),
+      beat_grid: Some(BeatGrid::default()),
+      syllabification: Some(SyllabificationSettings::default()),
+      text_fit: Some(TextFitSettings::default()),
     }
   }
 }
@@ -241,14 +284,18 @@ pub struct LoadDialog;
 
 pub struct TitlecardFilePathDialog;
 
+pub struct FontFilePathDialog;
+
 pub fn configure_file_dialog_plugin(plugin: FileDialogPlugin) -> FileDialogPlugin {
   plugin.with_load_file::<crate::project::LoadDialog>()
   .with_pick_file::<crate::project::TitlecardFilePathDialog>()
+  .with_pick_file::<crate::project::FontFilePathDialog>()
   .with_pick_file::<crate::project::NewProjectSongFileDialog>()
   .with_save_file::<crate::project::NewProjectSaveFileDialog>()
   .with_load_file::<crate::project::OpenProjectDialog>()
   .with_save_file::<crate::project::SaveAsDialog>()
   .with_pick_file::<SongFilePathDialog>()
+  .with_pick_file::<VocalFilePathDialog>()
 }
 
 fn handle_open_project_dialog(
@@ -258,6 +305,8 @@ fn handle_open_project_dialog(
   mut images: ResMut<Assets<Image>>,
   mut egui_user_textures: ResMut<EguiUserTextures>,
   mut titlecard_state: ResMut<crate::editor::TitlecardState>,
+  mut fonts: ResMut<Assets<Font>>,
+  mut font_state: ResMut<crate::editor::FontState>,
 ) {
   for ev in events.read() { 
     editor_state.project_file_path = ev.path.clone();
@@ -271,6 +320,39 @@ fn handle_open_project_dialog(
       if data.song_delay_time.is_none() {
         data.song_delay_time = Some(10.);
       }
+      if data.layout.is_none() {
+        data.layout = Some(LyricLayout::default());
+      }
+      if data.style.is_none() {
+        data.style = Some(LyricStyle::default());
+      }
+      if data.titlecard.is_none() {
+        data.titlecard = Some(TitlecardSettings::default());
+      }
+      if data.singers.is_none() {
+        data.singers = Some(SingerSettings::default());
+      }
+      if data.countdown.is_none() {
+        data.countdown = Some(CountdownSettings::default());
+      }
+      if data.writing.is_none() {
+        data.writing = Some(WritingSettings::default());
+      }
+      if data.guide_vocal.is_none() {
+        data.guide_vocal = Some(GuideVocalSettings::default());
+      }
+      if data.vocal_removal.is_none() {
+        data.vocal_removal = Some(VocalRemovalSettings::default());
+      }
+      if data.beat_grid.is_none() {
+        data.beat_grid = Some(BeatGrid::default());
+      }
+      if data.syllabification.is_none() {
+        data.syllabification = Some(SyllabificationSettings::default());
+      }
+      if data.text_fit.is_none() {
+        data.text_fit = Some(TextFitSettings::default());
+      }
       editor_state.project_data = Some(data);
       editor_state.lyrics_dirty = true;
       editor_state.is_paused = true;
@@ -279,6 +361,11 @@ fn handle_open_project_dialog(
         println!("couldn't deserialize file");
     }
 
+    font_state.lyric_font = None;
+    if let Some(font_path) = editor_state.project_data.as_ref().unwrap().font_path.clone() {
+      font_state.lyric_font = load_font(&font_path, fonts.as_mut(), editor_state.as_mut());
+    }
+
     if let Some(titlecard_path) = editor_state.project_data.as_ref().unwrap().thumbnail_path.clone() {
       let load_result = load_titlecard_image(&titlecard_path, images.as_mut(), egui_user_textures.as_mut(), editor_state.as_mut());
       let Some((image_handle, egui_texture_id)) = load_result else {
@@ -331,6 +418,45 @@ fn load_titlecard_image(titlecard_path: &PathBuf, images: &mut Assets<Image>,
   Some((image_handle, egui_texture_id))
 }
 
+pub fn load_font(font_path: &PathBuf, fonts: &mut Assets<Font>, editor_state: &mut EditorState) 
+  -> Option<Handle<Font>>
+{
+  let read_result = std::fs::read(font_path);
+  let Ok(font_data) = read_result else {
+    show_and_log_error(editor_state, 
+      format!("Error reading font file: {:?}", read_result.err().unwrap()));
+    return None;
+  };
+
+  let font_result = Font::try_from_bytes(font_data);
+  let Ok(font) = font_result else {
+    show_and_log_error(editor_state, 
+      format!("Error parsing font file: {:?}", font_result.err().unwrap()));
+    return None;
+  };
+
+  Some(fonts.add(font))
+}
+
+fn handle_font_file_path_dialog(
+  mut events: EventReader<DialogFilePicked<FontFilePathDialog>>,
+  mut editor_state: NonSendMut<EditorState>,
+  mut fonts: ResMut<Assets<Font>>,
+  mut font_state: ResMut<crate::editor::FontState>
+) {
+  for ev in events.read() {
+    let Some(font_handle) = load_font(&ev.path, fonts.as_mut(), editor_state.as_mut()) else {
+      return;
+    };
+
+    font_state.lyric_font = Some(font_handle);
+    if let Some(project_data) = editor_state.project_data.as_mut() {
+      project_data.font_path = Some(ev.path.clone());
+    }
+    editor_state.needs_save_before_exit = true;
+  }
+}
+
 fn handle_thumbnail_file_path_dialog(
   mut events: EventReader<DialogFilePicked<TitlecardFilePathDialog>>,
   mut egui_user_textures: ResMut<EguiUserTextures>,
@@ -379,11 +505,36 @@ pub fn file_ops_menu_ui(mut ui: InMut<egui::Ui>,
 
 pub fn project_menu_ui(mut ui: InMut<egui::Ui>,
   mut project_settings_dialog: ResMut<ProjectSettingsDialog>,
+  mut background_dialog: ResMut<BackgroundDialog>,
+  mut theme_dialog: ResMut<ThemeDialog>,
+  mut lyrics_import_dialog: ResMut<LyricsImportDialog>,
+  vocal_removal_state: Res<VocalRemovalState>,
+  mut generate_instrumental_events: EventWriter<GenerateInstrumentalRequestedEvent>,
   mut commands: Commands
 ) {
   if ui.button("Project Settings...").clicked() {
     project_settings_dialog.open();
   }
+  if ui.button("Stage Background...").clicked() {
+    background_dialog.open();
+  }
+  if ui.button("Themes...").clicked() {
+    theme_dialog.open();
+  }
+  let generate_instrumental_text = if vocal_removal_state.is_generating() { 
+    "Generating Instrumental..." 
+  } else { 
+    "Generate Instrumental" 
+  };
+  if ui.add_enabled(!vocal_removal_state.is_generating(), egui::Button::new(generate_instrumental_text)).clicked() {
+    generate_instrumental_events.send_default();
+  }
+  if ui.button("Import Lyrics...").clicked() {
+    lyrics_import_dialog.open();
+  }
+  if ui.button("Import Alignment...").clicked() {
+    commands.dialog().add_filter("Word alignment", &["json", "TextGrid"]).load_file::<crate::alignment::AlignmentImportDialog>();
+  }
   if ui.button("Export...").clicked() {
     commands.dialog().add_filter("Video file", &["mp4"]).save_file::<crate::export::ExportFilePathDialog>(Vec::new());
   }
@@ -442,9 +593,22 @@ struct ProjectSettingsProperties {
   pub unsung_color: Color,
   pub sung_color: Color,
   pub titlecard_show_time: f32,
+  pub titlecard: TitlecardSettings,
   pub song_delay_time: f32,
+  pub layout: LyricLayout,
+  pub font_path: FontFilePath,
+  pub style: LyricStyle,
+  pub singers: SingerSettings,
+  pub countdown: CountdownSettings,
+  pub writing: WritingSettings,
+  pub guide_vocal: GuideVocalSettings,
+  pub vocal_removal: VocalRemovalSettings,
+  pub beat_grid: BeatGrid,
+  pub syllabification: SyllabificationSettings,
+  pub text_fit: TextFitSettings,
   pub titlecard_path: TitlecardPath,
-  pub song_path: SongFilePath
+  pub song_path: SongFilePath,
+  pub vocal_path: VocalFilePath
 }
 
 impl ProjectSettingsProperties {
@@ -454,9 +618,22 @@ impl ProjectSettingsProperties {
       unsung_color: project_data.unsung_color.unwrap_or(Color::default()),
       sung_color: project_data.sung_color.unwrap_or(Color::default()),
       titlecard_show_time: project_data.titlecard_show_time.unwrap_or_default(),
+      titlecard: project_data.titlecard.clone().unwrap_or_default(),
       song_delay_time: project_data.song_delay_time.unwrap_or_default(),
+      layout: project_data.layout.clone().unwrap_or_default(),
+      font_path: FontFilePath(project_data.font_path.clone()),
+      style: project_data.style.clone().unwrap_or_default(),
+      singers: project_data.singers.clone().unwrap_or_default(),
+      countdown: project_data.countdown.clone().unwrap_or_default(),
+      writing: project_data.writing.clone().unwrap_or_default(),
+      guide_vocal: project_data.guide_vocal.clone().unwrap_or_default(),
+      vocal_removal: project_data.vocal_removal.clone().unwrap_or_default(),
+      beat_grid: project_data.beat_grid.clone().unwrap_or_default(),
+      syllabification: project_data.syllabification.clone().unwrap_or_default(),
+      text_fit: project_data.text_fit.clone().unwrap_or_default(),
       titlecard_path: TitlecardPath(project_data.thumbnail_path.clone()),
-      song_path: SongFilePath(project_data.song_file.clone())
+      song_path: SongFilePath(project_data.song_file.clone()),
+      vocal_path: VocalFilePath(project_data.vocal_file.clone())
     }
   }
 
@@ -465,7 +642,18 @@ impl ProjectSettingsProperties {
     project_data.unsung_color = Some(self.unsung_color);
     project_data.sung_color = Some(self.sung_color);
     project_data.titlecard_show_time = Some(self.titlecard_show_time);
+    project_data.titlecard = Some(self.titlecard.clone());
     project_data.song_delay_time = Some(self.song_delay_time);
+    project_data.layout = Some(self.layout.clone());
+    project_data.style = Some(self.style.clone());
+    project_data.singers = Some(self.singers.clone());
+    project_data.countdown = Some(self.countdown.clone());
+    project_data.writing = Some(self.writing.clone());
+    project_data.guide_vocal = Some(self.guide_vocal.clone());
+    project_data.vocal_removal = Some(self.vocal_removal.clone());
+    project_data.beat_grid = Some(self.beat_grid.clone());
+    project_data.syllabification = Some(self.syllabification.clone());
+    project_data.text_fit = Some(self.text_fit.clone());
     project_data.thumbnail_path = self.titlecard_path.0.clone();
   }
 }
@@ -538,6 +726,78 @@ impl InspectorPrimitive for SongFilePath {
 
 struct SongFilePathDialog;
 
+#[derive(Reflect, Clone)]
+struct VocalFilePath(Option<PathBuf>);
+
+impl InspectorPrimitive for VocalFilePath {
+  fn ui(&mut self, ui: &mut egui::Ui, options: &dyn std::any::Any, id: egui::Id,
+    mut env: InspectorUi<'_, '_>) -> bool 
+  {
+    self.ui_readonly(ui, options, id, env.reborrow());
+
+    if ui.button("Set").clicked() {
+      env.context.queue.as_mut().unwrap().push(|world: &mut World| {
+        world.commands().dialog().add_filter("Audio file", &["mp3", "wav", "ogg", "flac"]).pick_file_path::<VocalFilePathDialog>();
+      });
+    }
+    if self.0.is_some() && ui.button("Clear").clicked() {
+      env.context.queue.as_mut().unwrap().push(|world: &mut World| {
+        {
+          let mut editor_state = world.non_send_resource_mut::<EditorState>();
+          if let Some(project_data) = editor_state.project_data.as_mut() {
+            project_data.vocal_file = None;
+          }
+          editor_state.needs_save_before_exit = true;
+        }
+        world.non_send_resource_mut::<AudioState>().unload();
+      });
+    }
+
+    false
+  }
+
+  fn ui_readonly(&self, ui: &mut egui::Ui, options: &dyn std::any::Any,
+    id: egui::Id, env: InspectorUi<'_, '_>) 
+  {
+    if self.0.is_some() {
+      ui.label(self.0.as_ref().unwrap().as_os_str().to_string_lossy());
+    } else {
+      ui.label("None");
+    }
+  }
+}
+
+struct VocalFilePathDialog;
+
+#[derive(Reflect, Clone)]
+struct FontFilePath(Option<PathBuf>);
+
+impl InspectorPrimitive for FontFilePath {
+  fn ui(&mut self, ui: &mut egui::Ui, options: &dyn std::any::Any, id: egui::Id,
+    mut env: InspectorUi<'_, '_>) -> bool 
+  {
+    self.ui_readonly(ui, options, id, env.reborrow());
+
+    if ui.button("Set").clicked() {
+      env.context.queue.as_mut().unwrap().push(|world: &mut World| {
+        world.commands().dialog().add_filter("Font file", &["ttf", "otf"]).pick_file_path::<FontFilePathDialog>();
+      });
+    }
+
+    false
+  }
+
+  fn ui_readonly(&self, ui: &mut egui::Ui, options: &dyn std::any::Any,
+    id: egui::Id, env: InspectorUi<'_, '_>) 
+  {
+    if self.0.is_some() {
+      ui.label(self.0.as_ref().unwrap().as_os_str().to_string_lossy());
+    } else {
+      ui.label("Default");
+    }
+  }
+}
+
 fn handle_song_file_path_dialog(
   mut events: EventReader<DialogFilePicked<SongFilePathDialog>>,
   mut audio_state: NonSendMut<AudioState>,
@@ -546,10 +806,20 @@ fn handle_song_file_path_dialog(
   for ev in events.read() {
     if let Some(project_data) = &mut editor_state.project_data {
       project_data.song_file = Some(ev.path.clone());
-      if let Some(music_handle) = &mut audio_state.music_handle {
-        music_handle.pause(Tween::default());
-      }
-      audio_state.music_handle = None;
+      audio_state.unload();
+    }
+  }
+}
+
+fn handle_vocal_file_path_dialog(
+  mut events: EventReader<DialogFilePicked<VocalFilePathDialog>>,
+  mut audio_state: NonSendMut<AudioState>,
+  mut editor_state: NonSendMut<EditorState>
+) {
+  for ev in events.read() {
+    if let Some(project_data) = &mut editor_state.project_data {
+      project_data.vocal_file = Some(ev.path.clone());
+      audio_state.unload();
     }
   }
 }
\ No newline at end of file
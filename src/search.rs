@@ -0,0 +1,332 @@
+use std::borrow::Cow;
+use std::ops::Range;
+
+use bevy::prelude::*;
+use bevy_egui::egui;
+use regex::{Regex, RegexBuilder};
+
+use crate::lyrics::TIMECODE_PATTERN;
+
+pub struct SearchPlugin;
+
+impl Plugin for SearchPlugin {
+  fn build(&self, app: &mut App) {
+    app.insert_resource(LyricsSearch::default());
+  }
+}
+
+#[derive(Resource, Default)]
+pub struct LyricsSearch {
+  pub is_open: bool,
+  pub query: String,
+  pub replacement: String,
+  /// Allows `$1` style group references in the replacement.
+  pub use_regex: bool,
+  pub match_case: bool,
+  /// Matches across timestamps. Those matches can't be replaced.
+  pub ignore_timestamps: bool,
+  compiled_query: Option<CompiledQuery>,
+}
+
+/// The compiled search, kept until the query or its flags change.
+struct CompiledQuery {
+  query: String,
+  use_regex: bool,
+  match_case: bool,
+  regex: Result<Regex, String>,
+  timecode_regex: Regex,
+}
+
+/// A match of the search in the lyrics source.
+pub struct SearchMatch {
+  pub range: Range<usize>,
+  /// `None` if the match has timestamps inside it.
+  pub replacement: Option<String>,
+}
+
+/// What the search bar did this frame.
+#[derive(Default)]
+pub struct SearchBarResult {
+  /// Byte range of the lyrics to select in the editor.
+  pub selection: Option<Range<usize>>,
+  /// How many matches were replaced.
+  pub replaced_count: usize,
+}
+
+impl LyricsSearch {
+  fn update_compiled_query(&mut self) {
+    let is_up_to_date = self.compiled_query.as_ref().is_some_and(|compiled| {
+      compiled.query == self.query && compiled.use_regex == self.use_regex && compiled.match_case == self.match_case
+    });
+    if !is_up_to_date {
+      let pattern = if self.use_regex { self.query.clone() } else { regex::escape(&self.query) };
+      self.compiled_query = Some(CompiledQuery {
+        query: self.query.clone(),
+        use_regex: self.use_regex,
+        match_case: self.match_case,
+        regex: RegexBuilder::new(&pattern)
+          .case_insensitive(!self.match_case)
+          .build()
+          .map_err(|e| e.to_string()),
+        timecode_regex: Regex::new(TIMECODE_PATTERN).unwrap(),
+      });
+    }
+  }
+
+  /// Finds every match of the search in the lyrics source, in order.
+  pub fn find_matches(&mut self, lyrics: &str) -> Result<Vec<SearchMatch>, String> {
+    if self.query.is_empty() {
+      return Ok(Vec::new());
+    }
+
+    self.update_compiled_query();
+    let compiled = self.compiled_query.as_ref().unwrap();
+    let regex = compiled.regex.as_ref().map_err(|e| e.clone())?;
+
+    // the text that's searched, and where its runs of text between
+    // timestamps start in it and in the source
+    let (text, runs) = if self.ignore_timestamps {
+      let (text, runs) = strip_timecodes(lyrics, &compiled.timecode_regex);
+      (Cow::Owned(text), runs)
+    } else {
+      (Cow::Borrowed(lyrics), vec![(0, 0)])
+    };
+    let get_source_position = |position: usize| {
+      let (run_text_start, run_source_start) = runs[runs.partition_point(|(start, _)| *start <= position) - 1];
+      run_source_start + position - run_text_start
+    };
+
+    let matches = regex.captures_iter(&text)
+      .filter_map(|captures| {
+        let whole_match = captures.get(0).unwrap();
+        if whole_match.is_empty() {
+          return None;
+        }
+        let range = get_source_position(whole_match.start())..(get_source_position(whole_match.end() - 1) + 1);
+        if range.len() > whole_match.len() {
+          return Some(SearchMatch { range, replacement: None });
+        }
+
+        let mut replacement = String::new();
+        if self.use_regex {
+          captures.expand(&self.replacement, &mut replacement);
+        } else {
+          replacement.push_str(&self.replacement);
+        }
+        Some(SearchMatch { range, replacement: Some(replacement) })
+      })
+      .collect();
+    Ok(matches)
+  }
+}
+
+/// Removes timestamps. Returns the text and where each run of it starts in the text and the lyrics.
+fn strip_timecodes(lyrics: &str, timecode_regex: &Regex) -> (String, Vec<(usize, usize)>) {
+  let mut text = String::with_capacity(lyrics.len());
+  let mut runs = vec![(0, 0)];
+  let mut copied_up_to = 0;
+  for timecode in timecode_regex.find_iter(lyrics) {
+    text.push_str(&lyrics[copied_up_to..timecode.start()]);
+    copied_up_to = timecode.end();
+    runs.push((text.len(), copied_up_to));
+  }
+  text.push_str(&lyrics[copied_up_to..]);
+  (text, runs)
+}
+
+/// Gets the first match after the selection, wrapping around to the start.
+fn get_next_match(matches: &[SearchMatch], selection: &Option<Range<usize>>) -> Option<usize> {
+  let after = selection.as_ref().map(|selection| selection.end).unwrap_or(0);
+  matches.iter()
+    .position(|search_match| search_match.range.start >= after && Some(&search_match.range) != selection.as_ref())
+    .or(if matches.is_empty() { None } else { Some(0) })
+}
+
+/// Gets the last match before the selection, wrapping around to the end.
+fn get_previous_match(matches: &[SearchMatch], selection: &Option<Range<usize>>) -> Option<usize> {
+  let before = selection.as_ref().map(|selection| selection.start).unwrap_or(0);
+  matches.iter()
+    .rposition(|search_match| search_match.range.end <= before && Some(&search_match.range) != selection.as_ref())
+    .or(matches.len().checked_sub(1))
+}
+
+pub fn search_bar_ui(ui: &mut egui::Ui, search: &mut LyricsSearch, lyrics: &mut String,
+  selection: Option<Range<usize>>
+) -> SearchBarResult {
+  let mut result = SearchBarResult::default();
+  let mut find_next = false;
+
+  egui::Grid::new("lyrics_search_grid").num_columns(2).show(ui, |ui| {
+    ui.label("Find");
+    let query_response = ui.text_edit_singleline(&mut search.query);
+    if query_response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+      find_next = true;
+    }
+    ui.end_row();
+    ui.label("Replace");
+    ui.text_edit_singleline(&mut search.replacement);
+    ui.end_row();
+  });
+  ui.horizontal(|ui| {
+    ui.checkbox(&mut search.use_regex, "Regex");
+    ui.checkbox(&mut search.match_case, "Match Case");
+    ui.checkbox(&mut search.ignore_timestamps, "Ignore Timestamps");
+  });
+
+  let matches = match search.find_matches(lyrics) {
+    Ok(matches) => matches,
+    Err(e) => {
+      ui.colored_label(egui::Color32::RED, format!("Invalid pattern: {}", e));
+      return result;
+    }
+  };
+  let current_idx = matches.iter()
+    .position(|search_match| Some(&search_match.range) == selection.as_ref());
+
+  ui.horizontal(|ui| {
+    if ui.button("Previous").clicked() {
+      result.selection = get_previous_match(&matches, &selection).map(|idx| matches[idx].range.clone());
+    }
+    if ui.button("Next").clicked() || find_next {
+      result.selection = get_next_match(&matches, &selection).map(|idx| matches[idx].range.clone());
+    }
+    if ui.button("Replace").clicked() {
+      let current = current_idx.and_then(|idx| Some((&matches[idx].range, matches[idx].replacement.as_ref()?)));
+      match current {
+        Some((range, replacement)) => {
+          lyrics.replace_range(range.clone(), replacement);
+          result.replaced_count = 1;
+          // go on to the next match after what was just put in
+          let replaced_end = range.start + replacement.len();
+          let new_matches = search.find_matches(lyrics).unwrap_or_default();
+          result.selection = get_next_match(&new_matches, &Some(replaced_end..replaced_end))
+            .map(|idx| new_matches[idx].range.clone());
+        },
+        None => {
+          result.selection = get_next_match(&matches, &selection).map(|idx| matches[idx].range.clone());
+        }
+      }
+    }
+    let unreplaceable_count = matches.iter().filter(|search_match| search_match.replacement.is_none()).count();
+    if ui.add_enabled(matches.len() > unreplaceable_count, egui::Button::new("Replace All")).clicked() {
+      // replace from the end so earlier ranges stay valid
+      for search_match in matches.iter().rev() {
+        if let Some(replacement) = &search_match.replacement {
+          lyrics.replace_range(search_match.range.clone(), replacement);
+        }
+      }
+      result.replaced_count = matches.len() - unreplaceable_count;
+    }
+
+    if !search.query.is_empty() {
+      match current_idx {
+        Some(idx) => ui.label(format!("{} of {}", idx + 1, matches.len())),
+        None if matches.is_empty() => ui.label("No matches"),
+        None => ui.label(format!("{} matches", matches.len())),
+      };
+      if unreplaceable_count > 0 {
+        ui.label(format!("({} with timestamps inside can't be replaced)", unreplaceable_count));
+      }
+    }
+  });
+
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn search(query: &str, replacement: &str) -> LyricsSearch {
+    LyricsSearch {
+      query: query.into(),
+      replacement: replacement.into(),
+      ..default()
+    }
+  }
+
+  fn summarize(matches: &[SearchMatch]) -> Vec<(Range<usize>, Option<&str>)> {
+    matches.iter().map(|search_match| (search_match.range.clone(), search_match.replacement.as_deref())).collect()
+  }
+
+  fn matches_at(ranges: &[Range<usize>]) -> Vec<SearchMatch> {
+    ranges.iter().map(|range| SearchMatch { range: range.clone(), replacement: None }).collect()
+  }
+
+  #[test]
+  fn finds_text_ignoring_case() {
+    let mut search = search("la", "LA");
+    assert_eq!(summarize(&search.find_matches("La la").unwrap()), vec![(0..2, Some("LA")), (3..5, Some("LA"))]);
+    search.match_case = true;
+    assert_eq!(summarize(&search.find_matches("La la").unwrap()), vec![(3..5, Some("LA"))]);
+  }
+
+  #[test]
+  fn escapes_query_unless_regex() {
+    let mut search = search("(l)a", "${1}o");
+    assert!(search.find_matches("la").unwrap().is_empty());
+    search.use_regex = true;
+    assert_eq!(summarize(&search.find_matches("la").unwrap()), vec![(0..2, Some("lo"))]);
+  }
+
+  #[test]
+  fn invalid_regex_is_an_error() {
+    let mut search = search("(", "");
+    search.use_regex = true;
+    assert!(search.find_matches("(").is_err());
+  }
+
+  #[test]
+  fn updates_when_query_changes() {
+    let mut search = search("hello", "");
+    assert_eq!(search.find_matches("hello world").unwrap().len(), 1);
+    search.query = "world".into();
+    assert_eq!(summarize(&search.find_matches("hello world").unwrap()), vec![(6..11, Some(""))]);
+  }
+
+  #[test]
+  fn finds_text_across_timestamps() {
+    let lyrics = "[00:01.000]hel[00:01.500]lo [00:02.000]hello";
+    let mut search = search("hello", "hi");
+    assert_eq!(summarize(&search.find_matches(lyrics).unwrap()), vec![(39..44, Some("hi"))]);
+
+    search.ignore_timestamps = true;
+    // the first match has a timestamp inside, so it can't be replaced
+    assert_eq!(summarize(&search.find_matches(lyrics).unwrap()), vec![(11..27, None), (39..44, Some("hi"))]);
+  }
+
+  #[test]
+  fn timestamps_at_match_edges_are_left_out() {
+    let mut search = search("lo", "");
+    search.ignore_timestamps = true;
+    assert_eq!(summarize(&search.find_matches("hel[00:01.500]lo[00:02.000>]").unwrap()), vec![(14..16, Some(""))]);
+  }
+
+  #[test]
+  fn strips_timecodes() {
+    let timecode_regex = Regex::new(TIMECODE_PATTERN).unwrap();
+    let (text, runs) = strip_timecodes("[00:01.000]hel[00:01.500]lo[00:02.000>]", &timecode_regex);
+    assert_eq!(text, "hello");
+    assert_eq!(runs, vec![(0, 0), (0, 11), (3, 25), (5, 39)]);
+  }
+
+  #[test]
+  fn next_match_wraps_around() {
+    let matches = matches_at(&[0..2, 5..7]);
+    assert_eq!(get_next_match(&matches, &None), Some(0));
+    assert_eq!(get_next_match(&matches, &Some(0..2)), Some(1));
+    assert_eq!(get_next_match(&matches, &Some(3..3)), Some(1));
+    assert_eq!(get_next_match(&matches, &Some(5..7)), Some(0));
+    assert_eq!(get_next_match(&[], &None), None);
+  }
+
+  #[test]
+  fn previous_match_wraps_around() {
+    let matches = matches_at(&[0..2, 5..7]);
+    assert_eq!(get_previous_match(&matches, &None), Some(1));
+    assert_eq!(get_previous_match(&matches, &Some(5..7)), Some(0));
+    assert_eq!(get_previous_match(&matches, &Some(3..3)), Some(0));
+    assert_eq!(get_previous_match(&matches, &Some(0..2)), Some(1));
+    assert_eq!(get_previous_match(&[], &None), None);
+  }
+}
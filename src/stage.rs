@@ -1,11 +1,23 @@
 use bevy::prelude::*;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hasher};
 use std::time::Duration;
+use std::ops::Range;
 
-use crate::editor::EditorState;
+use crate::editor::{EditorState, FontState};
 use crate::export::ExportState;
 use bevy::render::view::RenderLayers;
+use bevy::text::{ComputedTextBlock, CosmicFontSystem, TextLayoutInfo, TextPipeline};
+use bevy::transform::TransformSystem;
+use bevy::render::camera::RenderTarget;
+use bevy::window::{PrimaryWindow, WindowCloseRequested, WindowRef};
 use crate::SubViewport;
-use bevy_egui::egui;
+use crate::sub_viewport::SubViewportView;
+use bevy_egui::{egui, EguiContexts};
+use serde::{Serialize, Deserialize};
+
+use crate::lyrics::{Block, BlockPlacement, ParsedLyrics, Ruby, SingerAssignment};
+use crate::project::ProjectData;
 
 pub struct StagePlugin;
 
@@ -13,8 +25,30 @@ impl Plugin for StagePlugin {
   fn build(&self, app: &mut App) {
     app.add_systems(Startup, startup)
       .add_systems(Update, (cleanup_preview, update_preview).chain())
+      .add_systems(Update, check_text_fit.before(update_preview))
+      .insert_resource(TextFitState::default())
+      .insert_resource(PreviewGuides::default())
+      .insert_resource(StageTextBounds::default())
+      .insert_resource(PreviewPanel::default())
+      .add_systems(Update, (handle_preview_window_close_requested, preview_window_ui).chain())
+      // needs the glyph layout of this frame's text, and has to spawn its 
+      // sprites before they're positioned and checked for visibility
+      .add_systems(PostUpdate, (position_ruby_text, update_wipe_overlays)
+        .chain()
+        .after(bevy::text::update_text2d_layout)
+        .before(TransformSystem::TransformPropagate))
+      .add_systems(PostUpdate, update_titlecard_text_bounds.after(bevy::text::update_text2d_layout))
       .add_event::<TitlecardUpdatedEvent>()
-      .add_systems(Update, handle_titlecard_updated);
+      .add_systems(Update, handle_titlecard_updated)
+      .add_systems(Update, update_titlecard.after(handle_titlecard_updated))
+      .register_type::<TitlecardSettings>()
+      .register_type::<LyricLayout>()
+      .register_type::<LyricStyle>()
+      .register_type::<SingerSettings>()
+      .register_type::<Singer>()
+      .register_type::<CountdownSettings>()
+      .register_type::<WritingSettings>()
+      .register_type::<TextFitSettings>();
   }
 }
 
@@ -24,12 +58,1122 @@ struct PreviewText;
 #[derive(Component)]
 struct LineText;
 
+#[derive(Serialize, Deserialize, Reflect, Clone)]
+pub struct LyricLayout {
+  /// Blocks take turns in the line slots.
+  pub line_count: usize,
+  pub placement: LinePlacement,
+  /// In pixels.
+  pub line_gap: f32,
+  /// Seconds a line appears before its first timestamp.
+  pub lead_in_time: f32,
+  /// Seconds a line stays up after its last timestamp.
+  pub linger_time: f32,
+}
+
+impl Default for LyricLayout {
+  fn default() -> Self {
+    Self {
+      line_count: 2,
+      placement: LinePlacement::Center,
+      line_gap: 160.,
+      lead_in_time: 3.,
+      linger_time: 0.5,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Reflect, Clone, Copy, PartialEq, Default)]
+pub enum LinePlacement {
+  Top,
+  #[default]
+  Center,
+  Bottom
+}
+
+impl From<BlockPlacement> for LinePlacement {
+  fn from(placement: BlockPlacement) -> Self {
+    match placement {
+      BlockPlacement::Top => LinePlacement::Top,
+      BlockPlacement::Center => LinePlacement::Center,
+      BlockPlacement::Bottom => LinePlacement::Bottom,
+    }
+  }
+}
+
+// distance kept between the stage edge and the outermost line slot
+const STAGE_EDGE_MARGIN: f32 = 160.;
+
+/// Size of the stage in pixels.
+pub const STAGE_SIZE: Vec2 = Vec2::new(1920., 1080.);
+/// Fraction of the stage inside the title-safe area.
+pub const TITLE_SAFE_FRACTION: f32 = 0.9;
+
+/// Fraction of the stage inside the action-safe area.
+pub const ACTION_SAFE_FRACTION: f32 = 0.93;
+
+/// Gets the title-safe area in stage coordinates.
+pub fn get_title_safe_rect() -> Rect {
+  Rect::from_center_size(Vec2::ZERO, STAGE_SIZE * TITLE_SAFE_FRACTION)
+}
+
+/// Gets the action-safe area in stage coordinates.
+pub fn get_action_safe_rect() -> Rect {
+  Rect::from_center_size(Vec2::ZERO, STAGE_SIZE * ACTION_SAFE_FRACTION)
+}
+
+impl LyricLayout {
+  /// Slot 0 is the topmost.
+  pub fn slot_position(&self, slot: usize) -> Vec2 {
+    let line_count = self.line_count.max(1);
+    let total_height = (line_count - 1) as f32 * self.line_gap;
+    let top_y = match self.placement {
+      LinePlacement::Top => 540. - STAGE_EDGE_MARGIN,
+      LinePlacement::Center => total_height / 2.,
+      LinePlacement::Bottom => -540. + STAGE_EDGE_MARGIN + total_height,
+    };
+    Vec2::new(0., top_y - slot as f32 * self.line_gap)
+  }
+}
+
+/// A block placed into a line slot, with the window it's visible for.
+#[derive(Debug, Clone)]
+pub struct ScheduledLine {
+  pub block_idx: usize,
+  pub slot: usize,
+  pub appear_time: Duration,
+  pub disappear_time: Duration,
+}
+
+/// Assigns each timed block to a line slot and works out when it appears and disappears.
+pub fn schedule_lines(lyrics: &ParsedLyrics, layout: &LyricLayout) -> Vec<ScheduledLine> {
+  let line_count = layout.line_count.max(1);
+  let lead_in_time = Duration::from_secs_f32(layout.lead_in_time.max(0.));
+  let linger_time = Duration::from_secs_f32(layout.linger_time.max(0.));
+
+  let timed_blocks = lyrics.blocks.iter().enumerate()
+    .filter_map(|(idx, block)| block.get_display_window(&lead_in_time, &linger_time).map(|window| (idx, window)))
+    .collect::<Vec<_>>();
+
+  let mut lines: Vec<ScheduledLine> = Vec::new();
+  for (n, (block_idx, window)) in timed_blocks.iter().enumerate() {
+    let slot = n % line_count;
+
+    let mut disappear_time = window.disappear;
+    if let Some((_, next_window)) = timed_blocks.get(n + line_count) {
+      disappear_time = disappear_time
+        .min(next_window.appear.max(window.last_sung))
+        .min(next_window.first_sung.max(window.first_sung));
+    }
+
+    let mut appear_time = window.appear;
+    if n >= line_count {
+      appear_time = appear_time.max(lines[n - line_count].disappear_time);
+    }
+
+    lines.push(ScheduledLine {
+      block_idx: *block_idx,
+      slot,
+      appear_time,
+      disappear_time
+    });
+  }
+
+  lines
+}
+
+/// Everything before `wipe_start` is sung, and `fraction` of the character after it.
+#[derive(Default, Debug)]
+struct WipeProgress {
+  wipe_start: usize,
+  wipe_end: usize,
+  fraction: f32,
+}
+
+fn get_wipe_progress(block: &Block, time: &Duration) -> WipeProgress {
+  if block.end_time().is_some_and(|end_time| *time >= end_time) {
+    return WipeProgress {
+      wipe_start: block.lyrics.len(),
+      wipe_end: block.lyrics.len(),
+      fraction: 0.
+    };
+  }
+
+  // the last syllable to have started, which may have finished
+  let Some(syllable) = block.get_syllables().into_iter().take_while(|syllable| syllable.start <= *time).last() else {
+    return WipeProgress::default();
+  };
+  if syllable.range.start > syllable.range.end || syllable.end < syllable.start
+    || syllable.range.end > block.lyrics.len()
+  {
+    warn!("non-sequential timestamps: {:?}", syllable);
+    return WipeProgress::default();
+  }
+
+  // finished syllables hold the wipe at their end until the next one starts
+  if *time >= syllable.end {
+    return WipeProgress {
+      wipe_start: syllable.range.end,
+      wipe_end: syllable.range.end,
+      fraction: 0.
+    };
+  }
+
+  let amount_sung = (*time - syllable.start).as_secs_f64() / syllable.duration().as_secs_f64();
+  let syllable_text = &block.lyrics[syllable.range.clone()];
+  let chars_sung = amount_sung * syllable_text.chars().count() as f64;
+  match syllable_text.char_indices().nth(chars_sung as usize) {
+    Some((idx, c)) => WipeProgress {
+      wipe_start: syllable.range.start + idx,
+      wipe_end: syllable.range.start + idx + c.len_utf8(),
+      fraction: chars_sung.fract() as f32
+    },
+    None => WipeProgress {
+      wipe_start: syllable.range.end,
+      wipe_end: syllable.range.end,
+      fraction: 0.
+    }
+  }
+}
+
+/// Text whose sung part is drawn over it by `update_wipe_overlays`.
+#[derive(Component)]
+struct WipeText {
+  span: Entity,
+  fraction: f32,
+  color: Color,
+  direction: WipeDirection,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum WipeDirection {
+  LeftToRight,
+  RightToLeft,
+  TopToBottom,
+}
+
+#[derive(Serialize, Deserialize, Reflect, Clone, Default)]
+pub struct WritingSettings {
+  pub direction: ReadingDirection,
+  /// Top to bottom, with lines going right to left. No ruby.
+  pub vertical: bool,
+}
+
+#[derive(Serialize, Deserialize, Reflect, Clone, Copy, PartialEq, Default)]
+pub enum ReadingDirection {
+  #[default]
+  Auto,
+  LeftToRight,
+  RightToLeft,
+}
+
+/// Positioned over `base_spans` by `position_ruby_text`.
+#[derive(Component)]
+struct RubyText {
+  base_text: Entity,
+  base_spans: Vec<Entity>,
+  /// For outline, shadow and bold copies.
+  offset: Vec2,
+}
+
+/// Size of ruby text relative to the text it annotates.
+const RUBY_SIZE_SCALE: f32 = 0.5;
+
+#[derive(Serialize, Deserialize, Reflect, Clone)]
+pub struct LyricStyle {
+  pub font_size: f32,
+  /// Draws the text twice, for fonts without a bold face.
+  pub faux_bold: bool,
+  /// Multiple of the font size.
+  pub line_spacing: f32,
+  pub outline_color: Color,
+  /// In pixels. Zero turns it off.
+  pub outline_width: f32,
+  pub shadow_color: Color,
+  /// In pixels, with positive y going up. Zero turns it off.
+  pub shadow_offset: Vec2,
+}
+
+impl Default for LyricStyle {
+  fn default() -> Self {
+    Self {
+      font_size: 64.,
+      faux_bold: false,
+      line_spacing: 1.2,
+      outline_color: Color::BLACK,
+      outline_width: 0.,
+      shadow_color: Color::srgba(0., 0., 0., 0.5),
+      shadow_offset: Vec2::ZERO,
+    }
+  }
+}
+
+// the outline is drawn as copies of the text nudged in each of these directions
+const OUTLINE_DIRECTIONS: [Vec2; 8] = [
+  Vec2::new(1., 0.),
+  Vec2::new(-1., 0.),
+  Vec2::new(0., 1.),
+  Vec2::new(0., -1.),
+  Vec2::new(0.7071, 0.7071),
+  Vec2::new(-0.7071, 0.7071),
+  Vec2::new(0.7071, -0.7071),
+  Vec2::new(-0.7071, -0.7071),
+];
+
+/// Gets the byte range of each line, without line breaks.
+fn get_line_ranges(text: &str) -> Vec<Range<usize>> {
+  let mut ranges = Vec::new();
+  let mut line_start = 0;
+  for (idx, c) in text.char_indices() {
+    if c == '\n' {
+      ranges.push(line_start..idx);
+      line_start = idx + 1;
+    }
+  }
+  if line_start < text.len() {
+    ranges.push(line_start..text.len());
+  }
+  ranges
+}
+
+#[derive(Serialize, Deserialize, Reflect, Clone)]
+pub struct CountdownSettings {
+  /// In seconds. The time before the first block counts as a gap.
+  pub gap_threshold: f32,
+  pub show_countdown: bool,
+  pub countdown_style: CountdownStyle,
+  /// In seconds.
+  pub countdown_time: f32,
+  pub countdown_color: Color,
+  pub show_instrumental_text: bool,
+  pub instrumental_text: String,
+}
+
+impl Default for CountdownSettings {
+  fn default() -> Self {
+    Self {
+      gap_threshold: 10.,
+      show_countdown: false,
+      countdown_style: CountdownStyle::Dots,
+      countdown_time: 3.,
+      countdown_color: Color::WHITE,
+      show_instrumental_text: false,
+      instrumental_text: "(instrumental)".into(),
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Reflect, Clone, Copy, PartialEq, Default)]
+pub enum CountdownStyle {
+  #[default]
+  Dots,
+  ProgressBar,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct GapIndicators {
+  /// Scheduled line index and seconds left.
+  countdowns: Vec<(usize, f32)>,
+  show_instrumental_text: bool,
+}
+
+fn get_gap_indicators(lyrics: &ParsedLyrics, lines: &[ScheduledLine], layout: &LyricLayout,
+  settings: &CountdownSettings, time: f32) -> GapIndicators
+{
+  let mut indicators = GapIndicators::default();
+  let mut prev_end = 0.;
+  for (line_idx, line) in lines.iter().enumerate() {
+    let Some(time_range) = lyrics.blocks[line.block_idx].get_time_range() else {
+      continue;
+    };
+    let start = time_range.start.as_secs_f32();
+    let end = time_range.end.as_secs_f32();
+
+    if start - prev_end >= settings.gap_threshold {
+      if settings.show_countdown && time >= start - settings.countdown_time && time < start {
+        indicators.countdowns.push((line_idx, start - time));
+      }
+
+      let text_end = start - layout.lead_in_time.max(settings.countdown_time);
+      if settings.show_instrumental_text && time >= prev_end + layout.linger_time && time < text_end {
+        indicators.show_instrumental_text = true;
+      }
+    }
+
+    prev_end = f32::max(prev_end, end);
+  }
+  indicators
+}
+
+fn spawn_gap_indicators(commands: &mut Commands, lyrics: &ParsedLyrics, lines: &[ScheduledLine],
+  project_data: &ProjectData, text_fit_state: &TextFitState, layout: &LyricLayout, style: &LyricStyle,
+  writing: &WritingSettings, font: &Handle<Font>, song_position: &Duration
+) {
+  let settings = project_data.countdown.clone().unwrap_or_default();
+  let indicators = get_gap_indicators(lyrics, lines, layout, &settings, song_position.as_secs_f32());
+
+  for (line_idx, time_left) in indicators.countdowns {
+    let line = &lines[line_idx];
+    let block = &lyrics.blocks[line.block_idx];
+    let (_, x_offset) = get_block_text_style(block, project_data, style, font.clone());
+    let center = get_block_center(block, line, layout, writing, x_offset);
+    // sit the countdown just above the block
+    let block_height = match text_fit_state.fitted_blocks.get(&line.block_idx) {
+      Some(fitted) => fitted.size.y,
+      None if writing.vertical => get_line_ranges(&block.lyrics).iter()
+        .map(|range| block.lyrics[range.clone()].chars().count())
+        .max()
+        .unwrap_or(1) as f32 * style.font_size * style.line_spacing,
+      None => get_line_ranges(&block.lyrics).len().max(1) as f32 * style.font_size * style.line_spacing,
+    };
+    let position = center + Vec2::new(0., block_height / 2. + style.font_size * 0.5);
+    spawn_countdown(commands, position, time_left, &settings);
+  }
+
+  if indicators.show_instrumental_text {
+    commands.spawn(
+      (
+        Text2d::new(settings.instrumental_text.clone()), 
+        TextFont {
+          font: font.clone(),
+          font_size: style.font_size,
+          ..Default::default()
+        },
+        TextColor(settings.countdown_color),
+        TextLayout::new_with_justify(JustifyText::Center),
+        RenderLayers::layer(1), 
+        PreviewText
+      )
+    );
+  }
+}
+
+fn spawn_countdown(commands: &mut Commands, position: Vec2, time_left: f32, settings: &CountdownSettings) {
+  match settings.countdown_style {
+    CountdownStyle::Dots => {
+      let dot_count = (settings.countdown_time.ceil() as usize).max(1);
+      let dots_left = (time_left.ceil() as usize).min(dot_count);
+      let spacing = 40.;
+      let first_x = position.x - (dots_left as f32 - 1.) * spacing / 2.;
+      for dot_idx in 0..dots_left {
+        commands.spawn((
+          Sprite::from_color(settings.countdown_color, Vec2::splat(24.)),
+          Transform::from_translation(Vec3::new(first_x + dot_idx as f32 * spacing, position.y, 0.)),
+          RenderLayers::layer(1),
+          PreviewText
+        ));
+      }
+    },
+    CountdownStyle::ProgressBar => {
+      let full_width = 480.;
+      let fraction = if settings.countdown_time > 0. { (time_left / settings.countdown_time).clamp(0., 1.) } else { 0. };
+      commands.spawn((
+        Sprite {
+          color: settings.countdown_color,
+          custom_size: Some(Vec2::new(full_width * fraction, 12.)),
+          anchor: bevy::sprite::Anchor::CenterLeft,
+          ..default()
+        },
+        Transform::from_translation(Vec3::new(position.x - full_width / 2., position.y, 0.)),
+        RenderLayers::layer(1),
+        PreviewText
+      ));
+    },
+  }
+}
+
+#[derive(Serialize, Deserialize, Reflect, Clone)]
+#[reflect(Default)]
+pub struct Singer {
+  pub name: String,
+  pub sung_color: Color,
+  pub unsung_color: Color,
+  pub position: SingerPosition,
+}
+
+impl Default for Singer {
+  fn default() -> Self {
+    Self {
+      name: "Singer".into(),
+      sung_color: Color::WHITE,
+      unsung_color: Color::srgb(0.5, 0.5, 0.5),
+      position: SingerPosition::Center,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Reflect, Clone, Copy, PartialEq, Default)]
+pub enum SingerPosition {
+  Left,
+  #[default]
+  Center,
+  Right,
+}
+
+impl SingerPosition {
+  fn x_offset(&self) -> f32 {
+    match self {
+      SingerPosition::Left => -480.,
+      SingerPosition::Center => 0.,
+      SingerPosition::Right => 480.,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Reflect, Clone)]
+pub struct SingerSettings {
+  pub singers: Vec<Singer>,
+  /// For lyrics sung by more than one singer.
+  pub together_sung_color: Color,
+  pub together_unsung_color: Color,
+}
+
+impl Default for SingerSettings {
+  fn default() -> Self {
+    Self {
+      singers: Vec::new(),
+      together_sung_color: Color::srgb(1., 0.9, 0.4),
+      together_unsung_color: Color::srgb(0.5, 0.45, 0.2),
+    }
+  }
+}
+
+impl SingerSettings {
+  /// Singers are referred to by 1-based number or by name, ignoring case.
+  pub fn resolve(&self, assignment: &SingerAssignment) -> Vec<usize> {
+    match assignment {
+      SingerAssignment::All => (0..self.singers.len()).collect(),
+      SingerAssignment::Singers(singers) => singers.iter()
+        .filter_map(|singer| {
+          singer.parse::<usize>().ok()
+            .filter(|number| *number >= 1 && *number <= self.singers.len())
+            .map(|number| number - 1)
+            .or_else(|| self.singers.iter().position(|s| s.name.eq_ignore_ascii_case(singer)))
+        })
+        .collect(),
+    }
+  }
+}
+
+struct SpanStyle {
+  position: usize,
+  sung_color: Color,
+  unsung_color: Color,
+  size_scale: f32,
+}
+
+struct BlockTextStyle<'a> {
+  style: &'a LyricStyle,
+  font: Handle<Font>,
+  /// Always starts at position 0.
+  span_styles: Vec<SpanStyle>,
+}
+
+impl BlockTextStyle<'_> {
+  fn get_span_style_at(&self, position: usize) -> &SpanStyle {
+    self.span_styles.iter()
+      .take_while(|span_style| span_style.position <= position)
+      .last()
+      .unwrap_or(&self.span_styles[0])
+  }
+
+  fn font_size_at(&self, position: usize) -> f32 {
+    self.style.font_size * self.get_span_style_at(position).size_scale
+  }
+}
+
+/// Gets a block's colors and sizes, and its x offset for the singer.
+fn get_block_text_style<'a>(block: &Block, project_data: &ProjectData, style: &'a LyricStyle,
+  font: Handle<Font>) -> (BlockTextStyle<'a>, f32)
+{
+  let singer_settings = project_data.singers.clone().unwrap_or_default();
+  let default_sung_color = project_data.sung_color.unwrap_or_default();
+  let default_unsung_color = project_data.unsung_color.unwrap_or_default();
+
+  let mut span_styles = vec![SpanStyle {
+    position: 0,
+    sung_color: default_sung_color,
+    unsung_color: default_unsung_color,
+    size_scale: 1.,
+  }];
+  let mut x_offset = 0.;
+  for change in &block.style_changes {
+    let singers = change.style.singers.as_ref()
+      .map(|assignment| singer_settings.resolve(assignment))
+      .unwrap_or_default();
+    let (sung_color, unsung_color) = match singers.as_slice() {
+      [] => (default_sung_color, default_unsung_color),
+      [singer] => (singer_settings.singers[*singer].sung_color, singer_settings.singers[*singer].unsung_color),
+      _ => (singer_settings.together_sung_color, singer_settings.together_unsung_color),
+    };
+    if change.position == 0 {
+      if let [singer] = singers.as_slice() {
+        x_offset = singer_settings.singers[*singer].position.x_offset();
+      }
+      span_styles.clear();
+    }
+    span_styles.push(SpanStyle {
+      position: change.position,
+      sung_color: change.style.sung_color.unwrap_or(sung_color),
+      unsung_color: change.style.unsung_color.unwrap_or(unsung_color),
+      size_scale: change.style.size.unwrap_or(1.),
+    });
+  }
+
+  (BlockTextStyle { style, font, span_styles }, x_offset)
+}
+
+/// One text entity per line, so the line spacing can be controlled.
+fn spawn_block_text(commands: &mut Commands, text: &str, ruby: &[Ruby], progress: &WipeProgress, 
+  center: Vec2, text_style: &BlockTextStyle, writing: &WritingSettings)
+{
+  let style = text_style.style;
+  let line_ranges = get_line_ranges(text);
+
+  if writing.vertical {
+    // each line is a column read top to bottom, with the columns going right 
+    // to left. Every character is its own text entity so they stack.
+    let column_width = style.font_size * style.line_spacing;
+    let right_x = center.x + (line_ranges.len().max(1) - 1) as f32 * column_width / 2.;
+    for (column_idx, range) in line_ranges.into_iter().enumerate() {
+      let char_ranges = text[range.clone()].char_indices()
+        .map(|(idx, c)| (range.start + idx)..(range.start + idx + c.len_utf8()))
+        .collect::<Vec<_>>();
+      let char_heights = char_ranges.iter()
+        .map(|char_range| text_style.font_size_at(char_range.start) * style.line_spacing)
+        .collect::<Vec<_>>();
+      let mut char_top = center.y + char_heights.iter().sum::<f32>() / 2.;
+
+      for (char_range, char_height) in char_ranges.into_iter().zip(char_heights) {
+        let position = Vec2::new(right_x - column_idx as f32 * column_width, char_top - char_height / 2.);
+        char_top -= char_height;
+        spawn_line_text(commands, text, char_range, &[], progress, position, text_style, 
+          WipeDirection::TopToBottom, None);
+      }
+    }
+    return;
+  }
+
+  let line_heights = get_line_heights(&line_ranges, ruby, text_style);
+  let total_height: f32 = line_heights.iter().map(|(text_height, ruby_height)| text_height + ruby_height).sum();
+  let mut line_top = center.y + total_height / 2.;
+
+  for (range, (line_height, ruby_height)) in line_ranges.into_iter().zip(line_heights) {
+    let position = Vec2::new(center.x, line_top - ruby_height - line_height / 2.);
+    line_top -= ruby_height + line_height;
+
+    let line_rubies = ruby.iter()
+      .filter(|ruby| range.contains(&ruby.range.start))
+      .collect::<Vec<_>>();
+    let right_to_left = match writing.direction {
+      ReadingDirection::Auto => is_right_to_left(&text[range.clone()]),
+      ReadingDirection::LeftToRight => false,
+      ReadingDirection::RightToLeft => true,
+    };
+    let wipe_direction = if right_to_left { WipeDirection::RightToLeft } else { WipeDirection::LeftToRight };
+    // forcing the direction is done with a directional mark at the start of 
+    // the line, which the text shaper treats as its first strong character
+    let direction_mark = match writing.direction {
+      ReadingDirection::Auto => None,
+      ReadingDirection::LeftToRight => Some("\u{200E}"),
+      ReadingDirection::RightToLeft => Some("\u{200F}"),
+    };
+
+    spawn_line_text(commands, text, range, &line_rubies, progress, position, text_style, 
+      wipe_direction, direction_mark);
+  }
+}
+
+/// Gets the height of each line of a block and of the ruby above it.
+fn get_line_heights(line_ranges: &[Range<usize>], ruby: &[Ruby], text_style: &BlockTextStyle) -> Vec<(f32, f32)> {
+  let style = text_style.style;
+  line_ranges.iter()
+    .map(|range| {
+      let max_size_scale = text_style.span_styles.iter()
+        .filter(|span_style| range.contains(&span_style.position))
+        .map(|span_style| span_style.size_scale)
+        .fold(text_style.get_span_style_at(range.start).size_scale, f32::max);
+      let ruby_height = ruby.iter()
+        .filter(|ruby| range.contains(&ruby.range.start))
+        .map(|ruby| text_style.font_size_at(ruby.range.start) * RUBY_SIZE_SCALE * style.line_spacing)
+        .fold(0., f32::max);
+      (style.font_size * max_size_scale * style.line_spacing, ruby_height)
+    })
+    .collect()
+}
+
+#[derive(Serialize, Deserialize, Reflect, Clone, Default)]
+pub struct TextFitSettings {
+  pub mode: TextFitMode,
+}
+
+#[derive(Serialize, Deserialize, Reflect, Clone, Copy, PartialEq, Default)]
+pub enum TextFitMode {
+  /// Lists blocks that don't fit under the lyrics editor.
+  #[default]
+  WarnOnly,
+  /// Breaks long lines, and shrinks the block if that isn't enough.
+  Wrap,
+  Shrink,
+}
+
+struct TextMeasurer<'a> {
+  fonts: &'a Assets<Font>,
+  text_pipeline: &'a mut TextPipeline,
+  font_system: &'a mut CosmicFontSystem,
+}
+
+impl TextMeasurer<'_> {
+  /// None if the font hasn't loaded yet.
+  fn line_width(&mut self, text: &str, range: Range<usize>, text_style: &BlockTextStyle) -> Option<f32> {
+    let mut boundaries = vec![range.start, range.end];
+    boundaries.extend(text_style.span_styles.iter()
+      .map(|span_style| span_style.position)
+      .filter(|position| range.contains(position)));
+    boundaries.sort();
+    boundaries.dedup();
+    let spans = boundaries.windows(2)
+      .map(|span_range| (&text[span_range[0]..span_range[1]], TextFont {
+        font: text_style.font.clone(),
+        font_size: text_style.font_size_at(span_range[0]),
+        ..default()
+      }))
+      .collect::<Vec<_>>();
+
+    let mut computed = ComputedTextBlock::default();
+    let measure = self.text_pipeline.create_text_measure(Entity::PLACEHOLDER, self.fonts,
+      spans.iter().map(|(span, font)| (Entity::PLACEHOLDER, 1, *span, font, Color::WHITE)),
+      1., &TextLayout::new(JustifyText::Center, LineBreak::NoWrap), &mut computed, self.font_system)
+      .ok()?;
+    Some(measure.max.x)
+  }
+
+  /// Includes the outline and shadow.
+  fn block_size(&mut self, text: &str, ruby: &[Ruby], text_style: &BlockTextStyle,
+    writing: &WritingSettings) -> Option<Vec2>
+  {
+    let style = text_style.style;
+    let line_ranges = get_line_ranges(text);
+    let size = if writing.vertical {
+      let column_height = |range: &Range<usize>| text[range.clone()].char_indices()
+        .map(|(idx, _)| text_style.font_size_at(range.start + idx) * style.line_spacing)
+        .sum::<f32>();
+      Vec2::new(line_ranges.len() as f32 * style.font_size * style.line_spacing,
+        line_ranges.iter().map(column_height).fold(0., f32::max))
+    } else {
+      let mut width = 0f32;
+      for range in line_ranges.iter() {
+        width = width.max(self.line_width(text, range.clone(), text_style)?);
+      }
+      let height = get_line_heights(&line_ranges, ruby, text_style).iter()
+        .map(|(text_height, ruby_height)| text_height + ruby_height)
+        .sum();
+      Vec2::new(width, height)
+    };
+
+    let bold_width = if style.faux_bold { style.font_size / 32. } else { 0. };
+    Some(size + Vec2::new(bold_width, 0.) + Vec2::splat(style.outline_width * 2.) + style.shadow_offset.abs())
+  }
+}
+
+/// Lets fitting be tested without fonts.
+trait MeasureBlockText {
+  fn line_width(&mut self, text: &str, range: Range<usize>) -> Option<f32>;
+  fn block_size(&mut self, text: &str) -> Option<Vec2>;
+}
+
+struct BlockMeasurer<'a, 'b> {
+  measurer: &'a mut TextMeasurer<'b>,
+  block: &'a Block,
+  text_style: &'a BlockTextStyle<'a>,
+  writing: &'a WritingSettings,
+}
+
+impl MeasureBlockText for BlockMeasurer<'_, '_> {
+  fn line_width(&mut self, text: &str, range: Range<usize>) -> Option<f32> {
+    self.measurer.line_width(text, range, self.text_style)
+  }
+
+  fn block_size(&mut self, text: &str) -> Option<Vec2> {
+    self.measurer.block_size(text, &self.block.ruby, self.text_style, self.writing)
+  }
+}
+
+struct FittedText {
+  unfitted_text: String,
+  text: String,
+  scale: f32,
+  size: Vec2,
+  /// Room around the block's center in the title-safe area.
+  available: Vec2,
+}
+
+impl FittedText {
+  fn fits(&self) -> bool {
+    // leave some slack for rounding when shrunk to fit exactly
+    self.size.x <= self.available.x + 0.5 && self.size.y <= self.available.y + 0.5
+  }
+}
+
+/// None if the font hasn't loaded yet.
+fn fit_block_text(measurer: &mut impl MeasureBlockText, text: &str, center: Vec2, mode: TextFitMode,
+  can_wrap: bool) -> Option<FittedText>
+{
+  let safe_rect = get_title_safe_rect();
+  let available = Vec2::new(
+    (center.x - safe_rect.min.x).min(safe_rect.max.x - center.x),
+    (center.y - safe_rect.min.y).min(safe_rect.max.y - center.y)
+  ).max(Vec2::ZERO) * 2.;
+  let fits = |size: Vec2| size.x <= available.x && size.y <= available.y;
+
+  let mut fitted_text = text.to_string();
+  let mut size = measurer.block_size(&fitted_text)?;
+  if mode == TextFitMode::Wrap && can_wrap && !fits(size) {
+    fitted_text = wrap_lines(measurer, &fitted_text, available.x)?;
+    size = measurer.block_size(&fitted_text)?;
+  }
+
+  let scale = if mode == TextFitMode::WarnOnly || fits(size) {
+    1.
+  } else {
+    (available / size).min_element().clamp(0., 1.)
+  };
+  Some(FittedText {
+    unfitted_text: text.to_string(),
+    text: fitted_text,
+    scale,
+    size: size * scale,
+    available
+  })
+}
+
+/// Swaps spaces for line breaks, so positions in the text don't change.
+fn wrap_lines(measurer: &mut impl MeasureBlockText, text: &str, max_width: f32) -> Option<String> {
+  let mut wrapped = text.to_string();
+  for range in get_line_ranges(text) {
+    let mut line_start = range.start;
+    let mut last_space: Option<usize> = None;
+    let word_ends = text[range.clone()].match_indices(' ')
+      .map(|(idx, _)| range.start + idx)
+      .chain(std::iter::once(range.end))
+      .collect::<Vec<_>>();
+    for word_end in word_ends {
+      if measurer.line_width(text, line_start..word_end)? > max_width {
+        if let Some(space) = last_space.filter(|space| *space >= line_start) {
+          wrapped.replace_range(space..(space + 1), "\n");
+          line_start = space + 1;
+        }
+      }
+      last_space = Some(word_end);
+    }
+  }
+  Some(wrapped)
+}
+
+fn get_text_fit_warning(block: &Block, fitted: &FittedText) -> Option<String> {
+  if fitted.fits() {
+    return None;
+  }
+  Some(format!("Line {}: block is {:.0}x{:.0} pixels, but only {:.0}x{:.0} fits in the title-safe area there",
+    block.line_range.start + 1, fitted.size.x, fitted.size.y, fitted.available.x, fitted.available.y))
+}
+
+#[derive(Resource, Default)]
+pub struct TextFitState {
+  pub warnings: Vec<String>,
+  /// Lyrics generation, font and settings hash the blocks were fitted for.
+  checked: Option<(u64, AssetId<Font>, u64)>,
+  /// By block index.
+  fitted_blocks: HashMap<usize, FittedText>,
+}
+
+/// Hashes serialized settings, since floats can't derive `Hash`.
+struct HashWriter<'a>(&'a mut DefaultHasher);
+
+impl std::io::Write for HashWriter<'_> {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    Hasher::write(self.0, buf);
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    Ok(())
+  }
+}
+
+fn check_text_fit(editor_state: NonSend<EditorState>,
+  font_state: Res<FontState>,
+  fonts: Res<Assets<Font>>,
+  mut text_pipeline: ResMut<TextPipeline>,
+  mut font_system: ResMut<CosmicFontSystem>,
+  mut text_fit_state: ResMut<TextFitState>
+) {
+  let (Some(lyrics), Some(project_data), false) = (editor_state.parsed_lyrics.as_ref(), 
+    editor_state.project_data.as_ref(), editor_state.lyrics_dirty) else {
+    return;
+  };
+
+  let font = font_state.lyric_font.clone().unwrap_or_default();
+  let mut settings_hasher = DefaultHasher::new();
+  let _ = serde_json::to_writer(HashWriter(&mut settings_hasher), &(&project_data.layout, &project_data.style,
+    &project_data.writing, &project_data.singers, &project_data.text_fit));
+  let checked = (editor_state.lyrics_generation, font.id(), settings_hasher.finish());
+  if text_fit_state.checked == Some(checked) {
+    return;
+  }
+  let layout = project_data.layout.clone().unwrap_or_default();
+  let style = project_data.style.clone().unwrap_or_default();
+  let writing = project_data.writing.clone().unwrap_or_default();
+  let text_fit = project_data.text_fit.clone().unwrap_or_default();
+  text_fit_state.fitted_blocks.clear();
+
+  let mut measurer = TextMeasurer {
+    fonts: &fonts,
+    text_pipeline: &mut text_pipeline,
+    font_system: &mut font_system,
+  };
+  let mut warnings = Vec::new();
+  let mut fitted_blocks = HashMap::new();
+  for line in schedule_lines(lyrics, &layout) {
+    let block = &lyrics.blocks[line.block_idx];
+    let (text_style, x_offset) = get_block_text_style(block, project_data, &style, font.clone());
+    let center = get_block_center(block, &line, &layout, &writing, x_offset);
+    let mut block_measurer = BlockMeasurer {
+      measurer: &mut measurer,
+      block,
+      text_style: &text_style,
+      writing: &writing,
+    };
+    // try again once the font has loaded
+    let Some(fitted) = fit_block_text(&mut block_measurer, &block.lyrics, center, text_fit.mode,
+      !writing.vertical) else
+    {
+      return;
+    };
+    warnings.extend(get_text_fit_warning(block, &fitted));
+    fitted_blocks.insert(line.block_idx, fitted);
+  }
+
+  text_fit_state.warnings = warnings;
+  text_fit_state.fitted_blocks = fitted_blocks;
+  text_fit_state.checked = Some(checked);
+}
+
+/// Spawns a line of text with its outline, shadow and ruby.
+fn spawn_line_text(commands: &mut Commands, text: &str, range: Range<usize>, line_rubies: &[&Ruby],
+  progress: &WipeProgress, position: Vec2, text_style: &BlockTextStyle, wipe_direction: WipeDirection,
+  direction_mark: Option<&str>)
+{
+  let style = text_style.style;
+  let wipe_start = progress.wipe_start.min(text.len());
+  let wipe_end = progress.wipe_end.clamp(wipe_start, text.len());
+
+  // split the line into spans wherever the wipe, the colors, the size or the 
+  // ruby change
+  let mut boundaries = vec![range.start, range.end, wipe_start, wipe_end];
+  boundaries.extend(text_style.span_styles.iter().map(|span_style| span_style.position));
+  boundaries.extend(line_rubies.iter().flat_map(|ruby| [ruby.range.start, ruby.range.end]));
+  boundaries.retain(|boundary| range.contains(boundary) || *boundary == range.end);
+  boundaries.sort();
+  boundaries.dedup();
+
+  // where the ruby for this line gets drawn relative to the text, and in what
+  // color if it's not the lyric colors
+  let mut ruby_copies: Vec<(Vec2, f32, Option<Color>)> = Vec::new();
+
+  if style.shadow_offset != Vec2::ZERO {
+    spawn_solid_text(commands, text, &boundaries, (position + style.shadow_offset).extend(-0.03),
+      text_style, style.shadow_color, direction_mark);
+    ruby_copies.push((style.shadow_offset, -0.03, Some(style.shadow_color)));
+  }
+
+  if style.outline_width > 0. {
+    for direction in OUTLINE_DIRECTIONS {
+      spawn_solid_text(commands, text, &boundaries, 
+        (position + direction * style.outline_width).extend(-0.02), text_style, style.outline_color,
+        direction_mark);
+      ruby_copies.push((direction * style.outline_width, -0.02, Some(style.outline_color)));
+    }
+  }
+
+  let mut base_text_ent = None;
+  let mut base_spans = Vec::new();
+  let bold_offsets = if style.faux_bold { vec![0., style.font_size / 32.] } else { vec![0.] };
+  for bold_offset in bold_offsets.iter() {
+    let preview_text_ent = commands.spawn(
+      (
+        Text2d::default(), 
+        TextLayout::new_with_justify(JustifyText::Center),
+        Transform::from_translation((position + Vec2::new(*bold_offset, 0.)).extend(0.)),
+        RenderLayers::layer(1), 
+        PreviewText
+      )
+    ).id();
+    ruby_copies.push((Vec2::new(*bold_offset, 0.), 0., None));
+
+    if let Some(direction_mark) = direction_mark {
+      spawn_text_span(commands, preview_text_ent, direction_mark, text_style, 
+        text_style.font_size_at(range.start), Color::NONE);
+    }
+
+    for span_range in boundaries.windows(2) {
+      let (start, end) = (span_range[0], span_range[1]);
+      let span_style = text_style.get_span_style_at(start);
+      let color = if end <= wipe_start { span_style.sung_color } else { span_style.unsung_color };
+      let span_ent = spawn_text_span(commands, preview_text_ent, &text[start..end], text_style,
+        text_style.font_size_at(start), color);
+
+      if base_text_ent.is_none() {
+        base_spans.push((start..end, span_ent));
+      }
+
+      if start == wipe_start && end == wipe_end {
+        commands.entity(preview_text_ent).insert(WipeText {
+          span: span_ent,
+          fraction: progress.fraction,
+          color: span_style.sung_color,
+          direction: wipe_direction,
+        });
+      }
+    }
+
+    base_text_ent.get_or_insert(preview_text_ent);
+  }
+
+  let Some(base_text_ent) = base_text_ent else {
+    return;
+  };
+  for ruby in line_rubies {
+    let ruby_base_spans = base_spans.iter()
+      .filter(|(span_range, _)| ruby.range.start <= span_range.start && span_range.end <= ruby.range.end)
+      .map(|(_, span_ent)| *span_ent)
+      .collect::<Vec<_>>();
+    let fraction = get_ruby_wipe_fraction(text, &ruby.range, wipe_start, wipe_end, progress.fraction);
+    let span_style = text_style.get_span_style_at(ruby.range.start);
+    let font_size = text_style.font_size_at(ruby.range.start) * RUBY_SIZE_SCALE;
+
+    for (offset, z, solid_color) in ruby_copies.iter() {
+      let color = match solid_color {
+        Some(color) => *color,
+        None if fraction >= 1. => span_style.sung_color,
+        None => span_style.unsung_color,
+      };
+      let ruby_text_ent = commands.spawn(
+        (
+          Text2d::default(), 
+          TextLayout::new_with_justify(JustifyText::Center),
+          Transform::from_translation((position + *offset).extend(*z)),
+          RubyText {
+            base_text: base_text_ent,
+            base_spans: ruby_base_spans.clone(),
+            offset: *offset,
+          },
+          RenderLayers::layer(1), 
+          PreviewText
+        )
+      ).id();
+      let span_ent = spawn_text_span(commands, ruby_text_ent, &ruby.text, text_style, font_size, color);
+
+      if solid_color.is_none() && fraction > 0. && fraction < 1. {
+        commands.entity(ruby_text_ent).insert(WipeText {
+          span: span_ent,
+          fraction,
+          color: span_style.sung_color,
+          direction: wipe_direction,
+        });
+      }
+    }
+  }
+}
+
+/// Goes by the first letter.
+fn is_right_to_left(text: &str) -> bool {
+  text.chars()
+    .find(|c| c.is_alphabetic())
+    .is_some_and(|c| matches!(c as u32, 
+      // hebrew, arabic, syriac, thaana, nko, samaritan, mandaic
+      0x0590..=0x08FF |
+      // hebrew and arabic presentation forms
+      0xFB1D..=0xFDFF | 0xFE70..=0xFEFF
+    ))
+}
+
+/// Keeps the ruby wipe in pace with the text under it.
+fn get_ruby_wipe_fraction(text: &str, ruby_range: &Range<usize>, wipe_start: usize, wipe_end: usize, 
+  fraction: f32) -> f32 
+{
+  let count_chars = |start: usize, end: usize| {
+    let (start, end) = (start.clamp(ruby_range.start, ruby_range.end), end.clamp(ruby_range.start, ruby_range.end));
+    text[start..end.max(start)].chars().count()
+  };
+  let total_chars = count_chars(ruby_range.start, ruby_range.end);
+  if total_chars == 0 {
+    return 0.;
+  }
+
+  let sung_chars = count_chars(ruby_range.start, wipe_start) as f32 
+    + count_chars(wipe_start, wipe_end) as f32 * fraction;
+  sung_chars / total_chars as f32
+}
+
+/// For outlines and shadows. Split the same as the line so sizes match.
+fn spawn_solid_text(commands: &mut Commands, text: &str, boundaries: &[usize], position: Vec3, 
+  text_style: &BlockTextStyle, color: Color, direction_mark: Option<&str>) 
+{
+  let solid_text_ent = commands.spawn(
+    (
+      Text2d::default(), 
+      TextLayout::new_with_justify(JustifyText::Center),
+      Transform::from_translation(position),
+      RenderLayers::layer(1), 
+      PreviewText
+    )
+  ).id();
+
+  if let (Some(direction_mark), Some(start)) = (direction_mark, boundaries.first()) {
+    spawn_text_span(commands, solid_text_ent, direction_mark, text_style, 
+      text_style.font_size_at(*start), Color::NONE);
+  }
+
+  for span_range in boundaries.windows(2) {
+    let (start, end) = (span_range[0], span_range[1]);
+    spawn_text_span(commands, solid_text_ent, &text[start..end], text_style,
+      text_style.font_size_at(start), color);
+  }
+}
+
+fn spawn_text_span(commands: &mut Commands, parent: Entity, text: &str, 
+  text_style: &BlockTextStyle, font_size: f32, color: Color) -> Entity 
+{
+  commands
+    .spawn(
+      (
+        TextSpan::new(text),
+        TextFont {
+          font: text_style.font.clone(),
+          font_size,
+          ..Default::default()
+        }, 
+        TextColor(color), 
+        LineText
+      )
+    )
+    .set_parent(parent)
+    .id()
+}
+
 fn startup(mut commands: Commands) {
   // create a subviewport for the video preview
   commands.spawn(SubViewport::new(RenderLayers::layer(1)));
 
   commands.spawn((Sprite::from_color(Color::NONE, [1920., 1080.].into()), 
     RenderLayers::layer(1), TitlecardStageSprite, Transform::from_translation([0., 0., 1.].into())));
+
+  let title_text_ent = commands.spawn((Text2d::default(), TextColor(Color::NONE), 
+    TextLayout::new_with_justify(JustifyText::Center), RenderLayers::layer(1), TitlecardTitleText,
+    Transform::from_translation([0., 0., 1.1].into()))).id();
+  commands.spawn((TextSpan::default(), TextColor(Color::NONE), TitlecardArtistText))
+    .set_parent(title_text_ent);
 }
 
 fn cleanup_preview(world: &mut World) {
@@ -38,120 +1182,393 @@ fn cleanup_preview(world: &mut World) {
     world.despawn(*entity);
   }
 
-  let preview_texts = world.query_filtered::<Entity, With<PreviewText>>().iter(&world).collect::<Vec<_>>();
-  for entity in preview_texts.iter() {
-    world.despawn(*entity);
+  let preview_texts = world.query_filtered::<Entity, With<PreviewText>>().iter(&world).collect::<Vec<_>>();
+  for entity in preview_texts.iter() {
+    world.despawn(*entity);
+  }
+}
+
+fn get_pre_delay_time(editor_state: &EditorState) -> f32 {
+  editor_state.project_data.as_ref()
+    .and_then(|project_data| project_data.song_delay_time)
+    .unwrap_or_default()
+}
+
+fn get_song_position(editor_state: &EditorState, export_state: &ExportState, 
+  audio_state: &crate::editor::AudioState) -> Duration 
+{
+  if export_state.is_exporting() {
+    Duration::from_secs_f64((export_state.frame_idx() as f64 / 12. - get_pre_delay_time(editor_state) as f64).max(0.))
+  } else {
+    audio_state.playhead_position()
   }
 }
 
+/// Includes the song pre-delay.
+fn get_stage_time(editor_state: &EditorState, export_state: &ExportState, 
+  audio_state: &crate::editor::AudioState) -> f32
+{
+  let curr_pre_delay_time = if export_state.is_exporting() {
+    (export_state.frame_idx() as f32 / 12.).clamp(0., get_pre_delay_time(editor_state))
+  } else {
+    editor_state.curr_pre_delay_time as f32
+  };
+  get_song_position(editor_state, export_state, audio_state).as_secs_f32() + curr_pre_delay_time
+}
+
 fn update_preview(editor_state: NonSend<EditorState>,
   export_state: Res<ExportState>,
   mut commands: Commands,
-  mut titlecard_stage_sprite_query: Query<&mut Sprite, With<TitlecardStageSprite>>,
   mut camera_tex_query: Query<&mut SubViewport>,
-  titlecard_state: Res<crate::editor::TitlecardState>,
-  mut audio_state: NonSendMut<crate::editor::AudioState>
+  font_state: Res<FontState>,
+  audio_state: NonSend<crate::editor::AudioState>,
+  text_fit_state: Res<TextFitState>,
+  mut stage_text_bounds: ResMut<StageTextBounds>
 )
 {
-  let mut pre_delay_time = 0.;
+  let mut lyric_bounds = Vec::new();
   if let Some(project_data) = &editor_state.project_data {
     camera_tex_query.single_mut().clear_color = ClearColorConfig::Custom(project_data.background_color.unwrap_or_default());
-    pre_delay_time = project_data.song_delay_time.unwrap();
   }
 
-  let song_position = if export_state.is_exporting() {
-    Duration::from_secs_f64((export_state.frame_idx() as f64 / 12. - pre_delay_time as f64).max(0.))
-  } else {
-    audio_state.playhead_position()
-  };
+  let song_position = get_song_position(&editor_state, &export_state, &audio_state);
 
-  let mut text: String = "".into();
-  let mut chars_sung: usize = 0;
-  if let Some(lyrics) = editor_state.parsed_lyrics.as_ref() {
-    if let Some(block) = lyrics.get_block_at_time(&song_position, &Duration::from_secs(3)) {
-      text = block.lyrics.clone();
-
-      if let Some((ts1, ts2)) = block.get_timestamps_surrounding(&song_position) {
-        if ts1.position <= ts2.position && ts1.time < ts2.time {
-          let elapsed_in_syl = song_position - ts1.time;
-          let total_syl_time = ts2.time - ts1.time;
-          let chars_in_syl = ts2.position - ts1.position;
-          let amount_sung = elapsed_in_syl.as_secs_f64() / total_syl_time.as_secs_f64();
-          chars_sung = (amount_sung * chars_in_syl as f64) as usize + ts1.position;
-        } else {
-          warn!("non-sequential timestamps: {:?}, {:?}", ts1, ts2);
-          chars_sung = 0
-        }
-      } else {
-        chars_sung = 0
+  if let (Some(lyrics), Some(project_data)) = (editor_state.parsed_lyrics.as_ref(), editor_state.project_data.as_ref()) {
+    let layout = project_data.layout.clone().unwrap_or_default();
+    let style = project_data.style.clone().unwrap_or_default();
+    let font = font_state.lyric_font.clone().unwrap_or_default();
+    let writing = project_data.writing.clone().unwrap_or_default();
+    let lines = schedule_lines(lyrics, &layout);
+    spawn_gap_indicators(&mut commands, lyrics, &lines, project_data, &text_fit_state, &layout, &style, &writing,
+      &font, &song_position);
+    for line in lines {
+      if song_position < line.appear_time || song_position >= line.disappear_time {
+        continue;
       }
+
+      let block = &lyrics.blocks[line.block_idx];
+      let progress = get_wipe_progress(block, &song_position);
+      let (text_style, x_offset) = get_block_text_style(block, project_data, &style, font.clone());
+      let center = get_block_center(block, &line, &layout, &writing, x_offset);
+      // the lyrics can be parsed again after they were last fitted, until
+      // they're fitted again
+      let fitted = text_fit_state.fitted_blocks.get(&line.block_idx)
+        .filter(|fitted| fitted.unfitted_text == block.lyrics);
+      let (text, scale) = match fitted {
+        Some(fitted) => {
+          lyric_bounds.push(Rect::from_center_size(center, fitted.size));
+          (fitted.text.as_str(), fitted.scale)
+        },
+        None => (block.lyrics.as_str(), 1.),
+      };
+      let fitted_style = LyricStyle {
+        font_size: style.font_size * scale,
+        ..style.clone()
+      };
+      let (text_style, _) = get_block_text_style(block, project_data, &fitted_style, font.clone());
+      spawn_block_text(&mut commands, text, &block.ruby, &progress, center, &text_style, &writing);
     }
   }
 
-  if text.len() > 2 && text.len() > chars_sung {
-    let preview_text_ent = commands.spawn(
-      (
-        Text2d::default(), 
-        TextLayout::new_with_justify(JustifyText::Center),
-        RenderLayers::layer(1), 
-        PreviewText
-      )
-    ).id();
-
-    commands
-      .spawn(
-        (
-          TextSpan::new(&text[0..chars_sung]),
-          TextFont {
-            font_size: 64.0,
-            ..Default::default()
-          }, 
-          TextColor(
-            editor_state.project_data.as_ref().unwrap().sung_color.unwrap_or_default()
-          ), 
-          LineText
-        )
-      )
-      .set_parent(preview_text_ent);
+  stage_text_bounds.lyrics = lyric_bounds;
+}
 
-    commands
-      .spawn(
-        (
-          TextSpan::new(String::from(&text[chars_sung..]) + "\n"),
-          TextFont {
-            font_size: 64.0,
-            
-            ..Default::default()
-          }, 
-          TextColor(
-            editor_state.project_data.as_ref().unwrap().unsung_color.unwrap_or_default()
-          ), 
-          LineText
-        )
-      )
-      .set_parent(preview_text_ent);
+fn get_block_center(block: &Block, line: &ScheduledLine, layout: &LyricLayout, writing: &WritingSettings,
+  x_offset: f32) -> Vec2
+{
+  // a [pos=...] tag puts the block on its own, outside the usual slots
+  let slot_position = match block.placement {
+    Some(placement) => LyricLayout {
+      line_count: 1,
+      placement: placement.into(),
+      ..layout.clone()
+    }.slot_position(0),
+    None => layout.slot_position(line.slot),
+  };
+  // vertical text uses the slots from right to left across the stage 
+  // instead of top to bottom, and ignores the singer's position
+  if writing.vertical {
+    Vec2::new(slot_position.y * 960. / 540., 0.)
+  } else {
+    slot_position + Vec2::new(x_offset, 0.)
   }
+}
 
-  let mut titlecard_stage_sprite_alpha = 0.0;
-  if titlecard_state.titlecard_image.is_some() {
-    let curr_pre_delay_time = if export_state.is_exporting {
-      (export_state.frame_idx() as f32 / 12.).clamp(0., pre_delay_time)
-    } else {
-      editor_state.curr_pre_delay_time as f32
+fn position_ruby_text(mut ruby_query: Query<(&RubyText, &TextLayoutInfo, &mut Transform)>,
+  base_query: Query<(&TextLayoutInfo, &ComputedTextBlock, &Transform), Without<RubyText>>,
+  windows: Query<&Window, With<PrimaryWindow>>
+) {
+  let scale_factor = windows.get_single()
+    .map(|window| window.resolution.scale_factor())
+    .unwrap_or(1.);
+
+  for (ruby_text, ruby_layout_info, mut transform) in ruby_query.iter_mut() {
+    let Ok((layout_info, computed_block, base_transform)) = base_query.get(ruby_text.base_text) else {
+      continue;
     };
-    titlecard_stage_sprite_alpha = (editor_state.project_data.as_ref().unwrap().titlecard_show_time.unwrap() 
-      - song_position.as_secs_f32() 
-      - curr_pre_delay_time).clamp(0.0, 1.0);
+    let span_indices = computed_block.entities().iter()
+      .enumerate()
+      .filter(|(_, text_entity)| ruby_text.base_spans.contains(&text_entity.entity))
+      .map(|(span_index, _)| span_index)
+      .collect::<Vec<_>>();
+
+    let glyphs = layout_info.glyphs.iter()
+      .filter(|glyph| span_indices.contains(&glyph.span_index))
+      .collect::<Vec<_>>();
+    if glyphs.is_empty() {
+      continue;
+    }
+
+    let left = glyphs.iter().map(|glyph| glyph.position.x - glyph.size.x / 2.).fold(f32::MAX, f32::min);
+    let right = glyphs.iter().map(|glyph| glyph.position.x + glyph.size.x / 2.).fold(f32::MIN, f32::max);
+    let origin = base_transform.translation.truncate() - layout_info.size / 2.;
+
+    transform.translation.x = origin.x + (left + right) / 2. / scale_factor + ruby_text.offset.x;
+    transform.translation.y = base_transform.translation.y + layout_info.size.y / 2. 
+      + ruby_layout_info.size.y / 2. + ruby_text.offset.y;
   }
+}
 
-  let titlecard_stage_sprite_color = Color::srgba(1.0, 1.0, 1.0, titlecard_stage_sprite_alpha);
+fn update_wipe_overlays(mut commands: Commands,
+  text_query: Query<(&WipeText, &TextLayoutInfo, &ComputedTextBlock, &Transform)>,
+  texture_atlases: Res<Assets<TextureAtlasLayout>>,
+  windows: Query<&Window, With<PrimaryWindow>>
+) {
+  // glyphs are laid out in physical pixels, and text is drawn scaled back down
+  // by the window's scale factor
+  let scale_factor = windows.get_single()
+    .map(|window| window.resolution.scale_factor())
+    .unwrap_or(1.);
+
+  for (wipe_text, layout_info, computed_block, transform) in text_query.iter() {
+    let Some(span_index) = computed_block.entities().iter()
+      .position(|text_entity| text_entity.entity == wipe_text.span) else {
+      continue;
+    };
+
+    let glyphs = layout_info.glyphs.iter()
+      .filter(|glyph| glyph.span_index == span_index)
+      .collect::<Vec<_>>();
+    if glyphs.is_empty() {
+      continue;
+    }
+
+    // a character can be shaped into more than one glyph, so wipe across all
+    // of them together
+    let left = glyphs.iter().map(|glyph| glyph.position.x - glyph.size.x / 2.).fold(f32::MAX, f32::min);
+    let right = glyphs.iter().map(|glyph| glyph.position.x + glyph.size.x / 2.).fold(f32::MIN, f32::max);
+    let top = glyphs.iter().map(|glyph| glyph.position.y + glyph.size.y / 2.).fold(f32::MIN, f32::max);
+    let bottom = glyphs.iter().map(|glyph| glyph.position.y - glyph.size.y / 2.).fold(f32::MAX, f32::min);
+    let origin = transform.translation.truncate() - layout_info.size / 2.;
+
+    for glyph in glyphs {
+      if glyph.size.x <= 0. || glyph.size.y <= 0. {
+        continue;
+      }
+      let Some(atlas) = texture_atlases.get(&glyph.atlas_info.texture_atlas) else {
+        continue;
+      };
+      let atlas_rect = atlas.textures[glyph.atlas_info.location.glyph_index].as_rect();
+      let glyph_min = glyph.position - glyph.size / 2.;
+      let glyph_max = glyph.position + glyph.size / 2.;
 
-  titlecard_stage_sprite_query.single_mut().color = titlecard_stage_sprite_color;
+      // the part of the glyph that's been wiped, in layout coordinates (y up)
+      // and in the atlas (y down)
+      let (wiped_rect, wiped_atlas_rect) = match wipe_text.direction {
+        WipeDirection::LeftToRight => {
+          let wipe_x = left + (right - left) * wipe_text.fraction;
+          let width = (wipe_x - glyph_min.x).clamp(0., glyph.size.x);
+          (Rect::new(glyph_min.x, glyph_min.y, glyph_min.x + width, glyph_max.y),
+            Rect::new(atlas_rect.min.x, atlas_rect.min.y, atlas_rect.min.x + width, atlas_rect.max.y))
+        },
+        WipeDirection::RightToLeft => {
+          let wipe_x = right - (right - left) * wipe_text.fraction;
+          let width = (glyph_max.x - wipe_x).clamp(0., glyph.size.x);
+          (Rect::new(glyph_max.x - width, glyph_min.y, glyph_max.x, glyph_max.y),
+            Rect::new(atlas_rect.max.x - width, atlas_rect.min.y, atlas_rect.max.x, atlas_rect.max.y))
+        },
+        WipeDirection::TopToBottom => {
+          let wipe_y = top - (top - bottom) * wipe_text.fraction;
+          let height = (glyph_max.y - wipe_y).clamp(0., glyph.size.y);
+          (Rect::new(glyph_min.x, glyph_max.y - height, glyph_max.x, glyph_max.y),
+            Rect::new(atlas_rect.min.x, atlas_rect.min.y, atlas_rect.max.x, atlas_rect.min.y + height))
+        },
+      };
+      if wiped_rect.is_empty() {
+        continue;
+      }
+
+      commands.spawn((
+        Sprite {
+          image: glyph.atlas_info.texture.clone(),
+          color: wipe_text.color,
+          rect: Some(wiped_atlas_rect),
+          custom_size: Some(wiped_rect.size() / scale_factor),
+          ..default()
+        },
+        Transform::from_translation((origin + wiped_rect.center() / scale_factor).extend(transform.translation.z + 0.01)),
+        RenderLayers::layer(1),
+        LineText
+      ));
+    }
+  }
 }
 
 #[derive(Component)]
 struct TitlecardStageSprite;
 
+#[derive(Component)]
+struct TitlecardTitleText;
+
+#[derive(Component)]
+struct TitlecardArtistText;
+
+#[derive(Serialize, Deserialize, Reflect, Clone)]
+pub struct TitlecardSettings {
+  pub fade_in_time: f32,
+  /// Ends at the titlecard show time.
+  pub fade_out_time: f32,
+  pub easing: FadeEasing,
+  pub fit_mode: ImageFitMode,
+  pub show_text_titlecard: bool,
+  pub text_color: Color,
+}
+
+impl Default for TitlecardSettings {
+  fn default() -> Self {
+    Self {
+      fade_in_time: 0.,
+      fade_out_time: 1.,
+      easing: FadeEasing::Linear,
+      fit_mode: ImageFitMode::Stretch,
+      show_text_titlecard: false,
+      text_color: Color::WHITE,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Reflect, Clone, Copy, PartialEq, Default)]
+pub enum FadeEasing {
+  #[default]
+  Linear,
+  EaseIn,
+  EaseOut,
+  EaseInOut,
+}
+
+impl FadeEasing {
+  pub fn apply(&self, t: f32) -> f32 {
+    let t = t.clamp(0., 1.);
+    match self {
+      FadeEasing::Linear => t,
+      FadeEasing::EaseIn => t * t,
+      FadeEasing::EaseOut => 1. - (1. - t) * (1. - t),
+      FadeEasing::EaseInOut => t * t * (3. - 2. * t),
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Reflect, Clone, Copy, PartialEq, Default)]
+pub enum ImageFitMode {
+  Contain,
+  Cover,
+  #[default]
+  Stretch,
+}
+
+impl ImageFitMode {
+  /// Gets the sprite size and source rect for an image.
+  pub fn fit(&self, image_size: Vec2, stage_size: Vec2) -> (Vec2, Option<Rect>) {
+    if image_size.x <= 0. || image_size.y <= 0. {
+      return (stage_size, None);
+    }
+    match self {
+      ImageFitMode::Contain => {
+        let scale = (stage_size.x / image_size.x).min(stage_size.y / image_size.y);
+        (image_size * scale, None)
+      },
+      ImageFitMode::Cover => {
+        let scale = (stage_size.x / image_size.x).max(stage_size.y / image_size.y);
+        let visible_size = stage_size / scale;
+        let min = (image_size - visible_size) / 2.;
+        (stage_size, Some(Rect::from_corners(min, min + visible_size)))
+      },
+      ImageFitMode::Stretch => (stage_size, None),
+    }
+  }
+}
+
+fn update_titlecard(editor_state: NonSend<EditorState>,
+  export_state: Res<ExportState>,
+  audio_state: NonSend<crate::editor::AudioState>,
+  titlecard_state: Res<crate::editor::TitlecardState>,
+  font_state: Res<crate::editor::FontState>,
+  images: Res<Assets<Image>>,
+  mut titlecard_stage_sprite_query: Query<&mut Sprite, With<TitlecardStageSprite>>,
+  mut title_text_query: Query<(&mut Text2d, &mut TextFont, &mut TextColor), (With<TitlecardTitleText>, Without<TitlecardArtistText>)>,
+  mut artist_text_query: Query<(&mut TextSpan, &mut TextFont, &mut TextColor), With<TitlecardArtistText>>
+) {
+  let Ok(mut sprite) = titlecard_stage_sprite_query.get_single_mut() else {
+    return;
+  };
+  let (mut title_text, mut title_font, mut title_color) = title_text_query.single_mut();
+  let (mut artist_text, mut artist_font, mut artist_color) = artist_text_query.single_mut();
+
+  let Some(project_data) = editor_state.project_data.as_ref() else {
+    sprite.color = Color::NONE;
+    title_color.0 = Color::NONE;
+    artist_color.0 = Color::NONE;
+    return;
+  };
+
+  let settings = project_data.titlecard.clone().unwrap_or_default();
+  let stage_time = get_stage_time(&editor_state, &export_state, &audio_state);
+  let show_time = project_data.titlecard_show_time.unwrap_or_default();
+
+  let fade_in = if settings.fade_in_time > 0. { stage_time / settings.fade_in_time } else { 1. };
+  let fade_out = if settings.fade_out_time > 0. { (show_time - stage_time) / settings.fade_out_time } else if stage_time < show_time { 1. } else { 0. };
+  let alpha = settings.easing.apply(fade_in.min(fade_out));
+
+  let stage_size = Vec2::new(1920., 1080.);
+  let mut text_alpha = 0.;
+  if let Some(titlecard_image) = titlecard_state.titlecard_image.as_ref() {
+    let image_size = images.get(titlecard_image).map(|image| image.size_f32()).unwrap_or(stage_size);
+    let (size, rect) = settings.fit_mode.fit(image_size, stage_size);
+    sprite.custom_size = Some(size);
+    sprite.rect = rect;
+    sprite.color = Color::srgba(1.0, 1.0, 1.0, alpha);
+  } else if settings.show_text_titlecard {
+    // cover the lyrics with the background color behind the title text
+    sprite.custom_size = Some(stage_size);
+    sprite.rect = None;
+    sprite.color = project_data.background_color.unwrap_or_default().with_alpha(alpha);
+    text_alpha = alpha;
+  } else {
+    sprite.color = Color::NONE;
+  }
+
+  if title_text.0 != project_data.title {
+    title_text.0 = project_data.title.clone();
+  }
+  let artist_line = format!("\n{}", project_data.artist);
+  if artist_text.0 != artist_line {
+    artist_text.0 = artist_line;
+  }
+
+  let font = font_state.lyric_font.clone().unwrap_or_default();
+  let font_size = project_data.style.as_ref().map_or(64., |style| style.font_size);
+  if title_font.font != font || title_font.font_size != font_size * 1.5 {
+    title_font.font = font.clone();
+    title_font.font_size = font_size * 1.5;
+  }
+  if artist_font.font != font || artist_font.font_size != font_size {
+    artist_font.font = font;
+    artist_font.font_size = font_size;
+  }
+  title_color.0 = settings.text_color.with_alpha(settings.text_color.alpha() * text_alpha);
+  artist_color.0 = title_color.0;
+}
+
 #[derive(Event, Default)]
 pub struct TitlecardUpdatedEvent;
 
@@ -173,14 +1590,571 @@ fn handle_titlecard_updated(mut events: EventReader<TitlecardUpdatedEvent>,
   }
 }
 
-pub fn preview_ui(mut ui: InMut<egui::Ui>, camera_tex_query: Query<&SubViewport>, export_state: Res<ExportState>) 
+/// Drawn by the editor over the stage image, so they're never exported.
+#[derive(Resource, Default)]
+pub struct PreviewGuides {
+  pub safe_areas: bool,
+  pub center_lines: bool,
+  pub text_bounds: bool,
+}
+
+#[derive(Resource, Default)]
+struct StageTextBounds {
+  lyrics: Vec<Rect>,
+  titlecard: Option<Rect>,
+}
+
+const TITLE_SAFE_GUIDE_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 200, 0);
+const ACTION_SAFE_GUIDE_COLOR: egui::Color32 = egui::Color32::from_rgb(0, 200, 255);
+const CENTER_GUIDE_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 0, 255);
+const TEXT_BOUNDS_GUIDE_COLOR: egui::Color32 = egui::Color32::from_rgb(0, 255, 0);
+
+fn draw_preview_guides(painter: &egui::Painter, rect: egui::Rect, guides: &PreviewGuides, text_bounds: &StageTextBounds) {
+  let scale = rect.width() / STAGE_SIZE.x;
+  // stage coordinates have their origin in the middle and y going up
+  let to_screen = |point: Vec2| rect.center() + egui::vec2(point.x, -point.y) * scale;
+  let to_screen_rect = |stage_rect: Rect| egui::Rect::from_two_pos(to_screen(stage_rect.min), to_screen(stage_rect.max));
+
+  if guides.safe_areas {
+    painter.rect_stroke(to_screen_rect(get_action_safe_rect()), 0., egui::Stroke::new(1., ACTION_SAFE_GUIDE_COLOR),
+      egui::StrokeKind::Middle);
+    painter.rect_stroke(to_screen_rect(get_title_safe_rect()), 0., egui::Stroke::new(1., TITLE_SAFE_GUIDE_COLOR),
+      egui::StrokeKind::Middle);
+  }
+  if guides.center_lines {
+    let stroke = egui::Stroke::new(1., CENTER_GUIDE_COLOR);
+    painter.line_segment([rect.center_top(), rect.center_bottom()], stroke);
+    painter.line_segment([rect.left_center(), rect.right_center()], stroke);
+  }
+  if guides.text_bounds {
+    for bounds in text_bounds.lyrics.iter().chain(text_bounds.titlecard.iter()) {
+      painter.rect_stroke(to_screen_rect(*bounds), 0., egui::Stroke::new(1., TEXT_BOUNDS_GUIDE_COLOR),
+        egui::StrokeKind::Outside);
+    }
+  }
+}
+
+fn update_titlecard_text_bounds(titlecard_text_query: Query<(&TextLayoutInfo, &Transform, &TextColor), With<TitlecardTitleText>>,
+  windows: Query<&Window, With<PrimaryWindow>>,
+  mut stage_text_bounds: ResMut<StageTextBounds>
+) {
+  // text is laid out in physical pixels
+  let scale_factor = windows.get_single()
+    .map(|window| window.resolution.scale_factor())
+    .unwrap_or(1.);
+  stage_text_bounds.titlecard = titlecard_text_query.get_single().ok()
+    .filter(|(_, _, color)| color.0.alpha() > 0.)
+    .map(|(layout_info, transform, _)| {
+      Rect::from_center_size(transform.translation.truncate(), layout_info.size / scale_factor)
+    });
+}
+
+#[derive(Resource, Default)]
+pub struct PreviewPanel {
+  view: SubViewportView,
+  /// Screen pixels per stage pixel.
+  zoom: f32,
+  window: Option<Entity>,
+  window_camera: Option<Entity>,
+}
+
+#[derive(Component)]
+pub struct PreviewWindow;
+
+impl PreviewPanel {
+  fn open_window(&mut self, commands: &mut Commands) {
+    let window = commands.spawn((
+      Window {
+        title: "YoteOke Preview".into(),
+        resolution: (960., 540.).into(),
+        ..default()
+      },
+      PreviewWindow
+    )).id();
+    // the preview is drawn with egui, so this camera only clears the window
+    let window_camera = commands.spawn((
+      Camera2d,
+      Camera {
+        target: RenderTarget::Window(WindowRef::Entity(window)),
+        clear_color: ClearColorConfig::Custom(Color::BLACK),
+        ..default()
+      },
+      RenderLayers::none()
+    )).id();
+    self.window = Some(window);
+    self.window_camera = Some(window_camera);
+  }
+
+  fn close_window(&mut self, commands: &mut Commands) {
+    for entity in [self.window.take(), self.window_camera.take()].into_iter().flatten() {
+      commands.entity(entity).despawn();
+    }
+  }
+}
+
+fn show_stage(ui: &mut egui::Ui, sub_viewport: &SubViewport, preview_panel: &mut PreviewPanel,
+  guides: &PreviewGuides, text_bounds: &StageTextBounds)
+{
+  let Some(shown) = sub_viewport.show(ui, &mut preview_panel.view) else {
+    return;
+  };
+  preview_panel.zoom = shown.zoom;
+  draw_preview_guides(&ui.painter_at(shown.response.rect), shown.image_rect, guides, text_bounds);
+}
+
+pub fn preview_ui(mut ui: InMut<egui::Ui>, camera_tex_query: Query<&SubViewport>, export_state: Res<ExportState>,
+  mut preview_guides: ResMut<PreviewGuides>,
+  mut preview_panel: ResMut<PreviewPanel>,
+  stage_text_bounds: Res<StageTextBounds>,
+  mut commands: Commands
+) 
 {
   egui::TopBottomPanel::top("preview_header").show_inside(&mut ui, |ui| {
-    if export_state.is_exporting() {
-      ui.label("Exporting...");
-    }
+    ui.horizontal(|ui| {
+      ui.checkbox(&mut preview_guides.safe_areas, "Safe Areas");
+      ui.checkbox(&mut preview_guides.center_lines, "Center Lines");
+      ui.checkbox(&mut preview_guides.text_bounds, "Text Bounds");
+      ui.separator();
+      if ui.button("Fit").on_hover_text("Fit the preview to the panel (double-click the preview)").clicked() {
+        preview_panel.view.fit();
+      }
+      if ui.button("100%").on_hover_text("Show one stage pixel per screen pixel").clicked() {
+        preview_panel.view.zoom = Some(1.);
+      }
+      ui.label(format!("{:.0}%", preview_panel.zoom * 100.));
+      ui.separator();
+      if preview_panel.window.is_some() {
+        if ui.button("Attach").clicked() {
+          preview_panel.close_window(&mut commands);
+        }
+      } else if ui.button("Detach").on_hover_text("Show the preview in its own window").clicked() {
+        preview_panel.open_window(&mut commands);
+      }
+      if export_state.is_exporting() {
+        ui.label("Exporting...");
+      }
+    });
   });
   egui::CentralPanel::default().show_inside(&mut ui, |ui| {
-    camera_tex_query.single().show(ui);
+    if preview_panel.window.is_some() {
+      ui.centered_and_justified(|ui| {
+        ui.label("The preview is in its own window.");
+      });
+      return;
+    }
+    show_stage(ui, camera_tex_query.single(), &mut preview_panel, &preview_guides, &stage_text_bounds);
+  });
+}
+
+fn preview_window_ui(mut egui_contexts: EguiContexts,
+  camera_tex_query: Query<&SubViewport>,
+  mut preview_panel: ResMut<PreviewPanel>,
+  preview_guides: Res<PreviewGuides>,
+  stage_text_bounds: Res<StageTextBounds>
+) {
+  let Some(window) = preview_panel.window else {
+    return;
+  };
+  // the window's egui context is set up the frame after it's spawned
+  let Some(ctx) = egui_contexts.try_ctx_for_entity_mut(window) else {
+    return;
+  };
+  egui::CentralPanel::default().frame(egui::Frame::NONE.fill(egui::Color32::BLACK)).show(ctx, |ui| {
+    show_stage(ui, camera_tex_query.single(), &mut preview_panel, &preview_guides, &stage_text_bounds);
   });
-}
\ No newline at end of file
+}
+
+fn handle_preview_window_close_requested(mut events: EventReader<WindowCloseRequested>,
+  mut preview_panel: ResMut<PreviewPanel>,
+  mut commands: Commands
+) {
+  for ev in events.read() {
+    if preview_panel.window == Some(ev.window) {
+      preview_panel.close_window(&mut commands);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse(lyrics: &str) -> ParsedLyrics {
+    ParsedLyrics::parse(&lyrics.to_string()).unwrap()
+  }
+
+  fn secs(secs: f32) -> Duration {
+    Duration::from_secs_f32(secs)
+  }
+
+  /// Every character is 10 pixels wide and every line 20 pixels tall.
+  struct MonospaceMeasurer;
+
+  impl MeasureBlockText for MonospaceMeasurer {
+    fn line_width(&mut self, text: &str, range: Range<usize>) -> Option<f32> {
+      Some(text[range].chars().count() as f32 * 10.)
+    }
+
+    fn block_size(&mut self, text: &str) -> Option<Vec2> {
+      let longest_line = text.lines().map(|line| line.chars().count()).max().unwrap_or(0);
+      Some(Vec2::new(longest_line as f32 * 10., text.lines().count() as f32 * 20.))
+    }
+  }
+
+  /// 1990 pixels wide, too wide for the title-safe area.
+  fn long_line() -> String {
+    vec!["word"; 40].join(" ")
+  }
+
+  fn layout(line_count: usize, lead_in_time: f32, linger_time: f32) -> LyricLayout {
+    LyricLayout {
+      line_count,
+      lead_in_time,
+      linger_time,
+      ..default()
+    }
+  }
+
+  #[test]
+  fn lines_alternate_between_slots() {
+    let lyrics = parse("[00:10.000]one[00:12.000]\n\nuntimed\n\n[00:14.000]two[00:16.000]\n\n[00:20.000]three[00:22.000]");
+    let lines = schedule_lines(&lyrics, &layout(2, 3., 0.5));
+    assert_eq!(lines.iter().map(|line| (line.block_idx, line.slot)).collect::<Vec<_>>(),
+      vec![(0, 0), (2, 1), (3, 0)]);
+    assert_eq!((lines[0].appear_time, lines[0].disappear_time), (secs(7.), secs(12.5)));
+    assert_eq!((lines[1].appear_time, lines[1].disappear_time), (secs(11.), secs(16.5)));
+    assert_eq!((lines[2].appear_time, lines[2].disappear_time), (secs(17.), secs(22.5)));
+  }
+
+  #[test]
+  fn line_gives_up_slot_for_next_lead_in() {
+    let lyrics = parse("[00:10.000]one[00:14.000]\n\n[00:16.000]two[00:18.000]");
+    let lines = schedule_lines(&lyrics, &layout(1, 3., 1.));
+    // the next line would appear at 13, but this one is sung until 14
+    assert_eq!(lines[0].disappear_time, secs(14.));
+    assert_eq!(lines[1].appear_time, secs(14.));
+  }
+
+  #[test]
+  fn single_timestamp_line_uses_lead_in_and_linger() {
+    let lines = schedule_lines(&parse("[00:10.000]Hey!"), &layout(1, 2., 1.));
+    assert_eq!((lines[0].appear_time, lines[0].disappear_time), (secs(8.), secs(11.)));
+  }
+
+  #[test]
+  fn lead_in_does_not_start_before_zero() {
+    let lines = schedule_lines(&parse("[00:01.000]early[00:02.000]"), &layout(1, 3., 0.));
+    assert_eq!(lines[0].appear_time, Duration::ZERO);
+  }
+
+  #[test]
+  fn untimed_blocks_are_not_scheduled() {
+    assert!(schedule_lines(&parse("no timestamps here"), &layout(2, 3., 1.)).is_empty());
+  }
+
+  #[test]
+  fn next_line_takes_over_slot_when_sung() {
+    let lyrics = parse("[00:10.000]one[00:20.000]\n\n[00:15.000]two[00:18.000]");
+    let lines = schedule_lines(&lyrics, &layout(1, 1., 1.));
+    assert_eq!((lines[0].appear_time, lines[0].disappear_time), (secs(9.), secs(15.)));
+    assert_eq!((lines[1].appear_time, lines[1].disappear_time), (secs(15.), secs(19.)));
+  }
+
+  #[test]
+  fn overlapping_blocks_share_the_stage() {
+    let lyrics = parse("[00:01.000]lead vocal[00:05.000]\n\n[00:03.000]backing[00:06.000]");
+    let lines = schedule_lines(&lyrics, &layout(2, 0., 0.));
+    assert_eq!(lines.iter().map(|line| (line.slot, line.appear_time, line.disappear_time)).collect::<Vec<_>>(),
+      vec![(0, secs(1.), secs(5.)), (1, secs(3.), secs(6.))]);
+  }
+
+  #[test]
+  fn wipe_progress_is_char_safe() {
+    let lyrics = parse("[00:01.000]かんじ[00:04.000]");
+    let block = &lyrics.blocks[0];
+
+    let progress = get_wipe_progress(block, &secs(0.5));
+    assert_eq!((progress.wipe_start, progress.wipe_end), (0, 0));
+
+    // halfway through the second character, which is bytes 3..6
+    let progress = get_wipe_progress(block, &secs(2.5));
+    assert_eq!((progress.wipe_start, progress.wipe_end), (3, 6));
+    assert_eq!(progress.fraction, 0.5);
+
+    let progress = get_wipe_progress(block, &secs(4.));
+    assert_eq!((progress.wipe_start, progress.wipe_end), (block.lyrics.len(), block.lyrics.len()));
+  }
+
+  #[test]
+  fn wipe_holds_at_syllable_end_during_gap() {
+    let lyrics = parse("[00:01.000]día[00:02.000>] [00:03.000]uno[00:04.000]");
+    let block = &lyrics.blocks[0];
+    let progress = get_wipe_progress(block, &secs(2.5));
+    assert_eq!((progress.wipe_start, progress.wipe_end), ("día".len(), "día".len()));
+  }
+
+  #[test]
+  fn wraps_at_spaces() {
+    assert_eq!(wrap_lines(&mut MonospaceMeasurer, "one two three four", 90.).unwrap(), "one two\nthree\nfour");
+    assert_eq!(wrap_lines(&mut MonospaceMeasurer, "one two", 90.).unwrap(), "one two");
+  }
+
+  #[test]
+  fn wrap_keeps_long_words_whole() {
+    assert_eq!(wrap_lines(&mut MonospaceMeasurer, "a extraordinarily b", 50.).unwrap(), "a\nextraordinarily\nb");
+  }
+
+  #[test]
+  fn wrap_keeps_line_breaks() {
+    assert_eq!(wrap_lines(&mut MonospaceMeasurer, "one two\nthree four", 70.).unwrap(), "one two\nthree\nfour");
+  }
+
+  #[test]
+  fn warn_only_leaves_text_alone() {
+    let fitted = fit_block_text(&mut MonospaceMeasurer, &long_line(), Vec2::ZERO, TextFitMode::WarnOnly, true)
+      .unwrap();
+    assert_eq!(fitted.text, long_line());
+    assert_eq!(fitted.scale, 1.);
+    assert_eq!(fitted.size, Vec2::new(1990., 20.));
+    assert_eq!(fitted.available, STAGE_SIZE * TITLE_SAFE_FRACTION);
+    assert!(!fitted.fits());
+  }
+
+  #[test]
+  fn shrinks_to_fit() {
+    let fitted = fit_block_text(&mut MonospaceMeasurer, &long_line(), Vec2::ZERO, TextFitMode::Shrink, true)
+      .unwrap();
+    assert_eq!(fitted.text, long_line());
+    assert!((fitted.scale - 1728. / 1990.).abs() < 0.0001);
+    assert!(fitted.fits());
+  }
+
+  #[test]
+  fn wraps_to_fit() {
+    let fitted = fit_block_text(&mut MonospaceMeasurer, &long_line(), Vec2::ZERO, TextFitMode::Wrap, true)
+      .unwrap();
+    // 34 words fit on the first line
+    assert_eq!(fitted.text.split('\n').map(|line| line.len()).collect::<Vec<_>>(), vec![169, 29]);
+    assert_eq!(fitted.scale, 1.);
+    assert_eq!(fitted.size, Vec2::new(1690., 40.));
+    assert!(fitted.fits());
+  }
+
+  #[test]
+  fn shrinks_when_wrapping_is_not_enough() {
+    // vertical text can't be wrapped
+    let fitted = fit_block_text(&mut MonospaceMeasurer, &long_line(), Vec2::ZERO, TextFitMode::Wrap, false)
+      .unwrap();
+    assert_eq!(fitted.text, long_line());
+    assert!(fitted.scale < 1.);
+    assert!(fitted.fits());
+
+    let word = "a".repeat(200);
+    let fitted = fit_block_text(&mut MonospaceMeasurer, &word, Vec2::ZERO, TextFitMode::Wrap, true).unwrap();
+    assert_eq!(fitted.text, word);
+    assert!(fitted.scale < 1.);
+    assert!(fitted.fits());
+  }
+
+  #[test]
+  fn less_room_near_edges() {
+    let fitted = fit_block_text(&mut MonospaceMeasurer, "word", Vec2::new(800., -400.), TextFitMode::WarnOnly, true)
+      .unwrap();
+    assert_eq!(fitted.available, Vec2::new(128., 172.));
+  }
+
+  #[test]
+  fn warns_when_block_does_not_fit() {
+    let lyrics = parse(&format!("[00:01.000]short[00:02.000]\n\n[00:03.000]{}[00:04.000]", long_line()));
+    let short = fit_block_text(&mut MonospaceMeasurer, &lyrics.blocks[0].lyrics, Vec2::ZERO, TextFitMode::WarnOnly,
+      true).unwrap();
+    assert_eq!(get_text_fit_warning(&lyrics.blocks[0], &short), None);
+    let long = fit_block_text(&mut MonospaceMeasurer, &lyrics.blocks[1].lyrics, Vec2::ZERO, TextFitMode::WarnOnly,
+      true).unwrap();
+    assert_eq!(get_text_fit_warning(&lyrics.blocks[1], &long).unwrap(),
+      "Line 3: block is 1990x20 pixels, but only 1728x972 fits in the title-safe area there");
+  }
+
+  const EASINGS: [FadeEasing; 4] = [FadeEasing::Linear, FadeEasing::EaseIn, FadeEasing::EaseOut, FadeEasing::EaseInOut];
+
+  #[test]
+  fn easings_start_at_zero_and_end_at_one() {
+    for easing in EASINGS {
+      assert_eq!(easing.apply(0.), 0.);
+      assert_eq!(easing.apply(1.), 1.);
+      // clamped outside the fade
+      assert_eq!(easing.apply(-1.), 0.);
+      assert_eq!(easing.apply(2.), 1.);
+    }
+  }
+
+  #[test]
+  fn easings_never_go_backwards() {
+    for easing in EASINGS {
+      let values = (0..=100).map(|step| easing.apply(step as f32 / 100.)).collect::<Vec<_>>();
+      assert!(values.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+    assert!(FadeEasing::EaseIn.apply(0.5) < 0.5);
+    assert!(FadeEasing::EaseOut.apply(0.5) > 0.5);
+    assert_eq!(FadeEasing::EaseInOut.apply(0.5), 0.5);
+  }
+
+  #[test]
+  fn contain_fits_whole_image() {
+    let (size, crop) = ImageFitMode::Contain.fit(Vec2::new(800., 600.), STAGE_SIZE);
+    assert_eq!((size, crop), (Vec2::new(1440., 1080.), None));
+    let (size, crop) = ImageFitMode::Contain.fit(Vec2::new(1000., 250.), STAGE_SIZE);
+    assert_eq!((size, crop), (Vec2::new(1920., 480.), None));
+  }
+
+  fn assert_crop_near(crop: Option<Rect>, min: Vec2, max: Vec2) {
+    let crop = crop.unwrap();
+    assert!((crop.min - min).abs().max_element() < 0.001, "{:?} isn't near {:?}", crop.min, min);
+    assert!((crop.max - max).abs().max_element() < 0.001, "{:?} isn't near {:?}", crop.max, max);
+  }
+
+  #[test]
+  fn cover_crops_to_fill_stage() {
+    // 4:3 loses some of its top and bottom
+    let (size, crop) = ImageFitMode::Cover.fit(Vec2::new(800., 600.), STAGE_SIZE);
+    assert_eq!(size, STAGE_SIZE);
+    assert_crop_near(crop, Vec2::new(0., 75.), Vec2::new(800., 525.));
+    // a wide image loses some of its sides
+    let (size, crop) = ImageFitMode::Cover.fit(Vec2::new(1000., 250.), STAGE_SIZE);
+    assert_eq!(size, STAGE_SIZE);
+    assert_crop_near(crop, Vec2::new(277.7778, 0.), Vec2::new(722.2222, 250.));
+  }
+
+  #[test]
+  fn stretch_fills_stage() {
+    assert_eq!(ImageFitMode::Stretch.fit(Vec2::new(800., 600.), STAGE_SIZE), (STAGE_SIZE, None));
+    // nothing to fit before the image has loaded
+    assert_eq!(ImageFitMode::Contain.fit(Vec2::ZERO, STAGE_SIZE), (STAGE_SIZE, None));
+  }
+
+  fn singer_settings(names: &[&str]) -> SingerSettings {
+    SingerSettings {
+      singers: names.iter().map(|name| Singer { name: name.to_string(), ..default() }).collect(),
+      ..default()
+    }
+  }
+
+  fn assignment(singers: &[&str]) -> SingerAssignment {
+    SingerAssignment::Singers(singers.iter().map(|singer| singer.to_string()).collect())
+  }
+
+  #[test]
+  fn resolves_singers_by_name_or_number() {
+    let settings = singer_settings(&["Alex", "Sam"]);
+    assert_eq!(settings.resolve(&assignment(&["Sam"])), vec![1]);
+    assert_eq!(settings.resolve(&assignment(&["Alex", "Sam"])), vec![0, 1]);
+    assert_eq!(settings.resolve(&assignment(&["2", "1"])), vec![1, 0]);
+    assert_eq!(settings.resolve(&SingerAssignment::All), vec![0, 1]);
+  }
+
+  #[test]
+  fn singer_names_ignore_case() {
+    let settings = singer_settings(&["Alex", "Sam"]);
+    assert_eq!(settings.resolve(&assignment(&["ALEX"])), vec![0]);
+    assert_eq!(settings.resolve(&assignment(&["sam"])), vec![1]);
+  }
+
+  #[test]
+  fn unknown_singers_are_skipped() {
+    let settings = singer_settings(&["Alex", "Sam"]);
+    assert_eq!(settings.resolve(&assignment(&["Jo"])), Vec::<usize>::new());
+    assert_eq!(settings.resolve(&assignment(&["Jo", "Sam"])), vec![1]);
+    // numbers past the last singer
+    assert_eq!(settings.resolve(&assignment(&["0", "3"])), Vec::<usize>::new());
+    assert_eq!(singer_settings(&[]).resolve(&SingerAssignment::All), Vec::<usize>::new());
+  }
+
+  fn countdown_settings() -> CountdownSettings {
+    CountdownSettings {
+      show_countdown: true,
+      show_instrumental_text: true,
+      ..default()
+    }
+  }
+
+  fn gap_indicators_at(lyrics: &ParsedLyrics, time: f32) -> GapIndicators {
+    let layout = layout(2, 3., 0.5);
+    get_gap_indicators(lyrics, &schedule_lines(lyrics, &layout), &layout, &countdown_settings(), time)
+  }
+
+  #[test]
+  fn counts_down_to_block_after_long_gap() {
+    let lyrics = parse("[00:20.000]late[00:22.000]");
+    assert_eq!(gap_indicators_at(&lyrics, 16.9).countdowns, vec![]);
+    assert_eq!(gap_indicators_at(&lyrics, 18.5).countdowns, vec![(0, 1.5)]);
+    assert_eq!(gap_indicators_at(&lyrics, 20.).countdowns, vec![]);
+  }
+
+  #[test]
+  fn instrumental_text_shows_until_lead_in() {
+    let lyrics = parse("[00:01.000]a[00:02.000]\n\n[00:20.000]b[00:22.000]");
+    // the linger after the first block
+    assert!(!gap_indicators_at(&lyrics, 2.2).show_instrumental_text);
+    assert!(gap_indicators_at(&lyrics, 2.5).show_instrumental_text);
+    assert!(gap_indicators_at(&lyrics, 16.9).show_instrumental_text);
+    // the countdown and lead-in before the second
+    assert!(!gap_indicators_at(&lyrics, 17.).show_instrumental_text);
+    assert!(!gap_indicators_at(&lyrics, 30.).show_instrumental_text);
+  }
+
+  #[test]
+  fn gap_threshold_is_inclusive() {
+    let lyrics = parse("[00:01.000]a[00:02.000]\n\n[00:12.000]exactly[00:13.000]\n\n[00:22.900]almost[00:24.000]");
+    assert_eq!(gap_indicators_at(&lyrics, 11.).countdowns, vec![(1, 1.)]);
+    assert_eq!(gap_indicators_at(&lyrics, 21.9), GapIndicators::default());
+  }
+
+  #[test]
+  fn gap_starts_when_every_earlier_block_ends() {
+    // the first block is still being sung when the second one ends
+    let lyrics = parse("[00:01.000]long[00:15.000]\n\n[00:02.000]short[00:03.000]\n\n[00:20.000]next[00:21.000]");
+    assert_eq!(gap_indicators_at(&lyrics, 19.), GapIndicators::default());
+  }
+
+  #[test]
+  fn indicators_can_be_turned_off() {
+    let lyrics = parse("[00:20.000]late[00:22.000]");
+    let layout = layout(2, 3., 0.5);
+    let settings = CountdownSettings {
+      show_countdown: false,
+      show_instrumental_text: false,
+      ..default()
+    };
+    assert_eq!(get_gap_indicators(&lyrics, &schedule_lines(&lyrics, &layout), &layout, &settings, 19.),
+      GapIndicators::default());
+    assert_eq!(get_gap_indicators(&lyrics, &schedule_lines(&lyrics, &layout), &layout, &settings, 10.),
+      GapIndicators::default());
+  }
+
+  #[test]
+  fn hebrew_and_arabic_read_right_to_left() {
+    assert!(is_right_to_left("שלום"));
+    assert!(is_right_to_left("مرحبا"));
+    // presentation forms
+    assert!(is_right_to_left("ﻣﺮﺣﺒﺎ"));
+    assert!(!is_right_to_left("hello"));
+    assert!(!is_right_to_left("かんじ"));
+  }
+
+  #[test]
+  fn first_letter_decides_direction() {
+    assert!(is_right_to_left("שלום world"));
+    assert!(!is_right_to_left("hello עולם"));
+    // leading digits and punctuation don't count
+    assert!(is_right_to_left("1, 2... שלום"));
+    assert!(!is_right_to_left("\"(3) hello"));
+  }
+
+  #[test]
+  fn text_without_letters_reads_left_to_right() {
+    assert!(!is_right_to_left(""));
+    assert!(!is_right_to_left("123 456"));
+    assert!(!is_right_to_left("١٢٣"));
+    assert!(!is_right_to_left("!?... -"));
+  }
+}
@@ -26,6 +26,34 @@ pub struct SubViewport {
 #[derive(Component)]
 struct SubViewportCamera;
 
+// size of the texture in pixels
+const TEXTURE_SIZE: egui::Vec2 = egui::Vec2::new(1920., 1080.);
+// the most screen pixels per texture pixel the view can be zoomed to
+const MAX_ZOOM: f32 = 8.;
+
+#[derive(Default, Clone)]
+pub struct SubViewportView {
+  /// Screen pixels per texture pixel, or None to fit.
+  pub zoom: Option<f32>,
+  /// Offset from centered, in points.
+  pub pan: egui::Vec2,
+}
+
+impl SubViewportView {
+  pub fn fit(&mut self) {
+    self.zoom = None;
+    self.pan = egui::Vec2::ZERO;
+  }
+}
+
+pub struct SubViewportResponse {
+  /// The space the texture was clipped to.
+  pub response: egui::Response,
+  /// The whole texture, which can run outside the space when zoomed in.
+  pub image_rect: egui::Rect,
+  pub zoom: f32,
+}
+
 impl SubViewport {
   pub fn new(render_layers: RenderLayers) -> Self {
     Self {
@@ -110,17 +138,57 @@ impl SubViewport {
     }
   }
 
-  pub fn show(&self, ui: &mut egui::Ui) {
-    if let Some(egui_texture_id) = &self.egui_texture_id {
-      let available_size = ui.available_size();
-      ui.image(egui::load::SizedTexture::new(
-        *egui_texture_id,
-        egui::vec2(
-            available_size.x,
-            available_size.x * 1080. / 1920.
-        )
-      ));
+  /// Ctrl+scroll or pinching zooms, dragging pans and double-clicking fits.
+  pub fn show(&self, ui: &mut egui::Ui, view: &mut SubViewportView) -> Option<SubViewportResponse> {
+    let egui_texture_id = self.egui_texture_id?;
+    let (rect, response) = ui.allocate_exact_size(ui.available_size(), egui::Sense::click_and_drag());
+    let pixels_per_point = ui.ctx().pixels_per_point();
+
+    if response.double_clicked() {
+      view.fit();
     }
+
+    // points per texture pixel
+    let fit_scale = (rect.width() / TEXTURE_SIZE.x).min(rect.height() / TEXTURE_SIZE.y).max(0.);
+    let mut scale = view.zoom.map_or(fit_scale, |zoom| zoom / pixels_per_point);
+
+    let zoom_delta = ui.input(|i| i.zoom_delta());
+    if response.hovered() && zoom_delta != 1. {
+      let new_scale = (scale * zoom_delta).min(MAX_ZOOM / pixels_per_point);
+      if new_scale <= fit_scale {
+        view.fit();
+        scale = fit_scale;
+      } else {
+        // keep the point under the pointer where it is
+        if let Some(pointer) = response.hover_pos() {
+          let center = rect.center() + view.pan;
+          view.pan = pointer - (pointer - center) * new_scale / scale - rect.center();
+        }
+        view.zoom = Some(new_scale * pixels_per_point);
+        scale = new_scale;
+      }
+    }
+    if response.dragged() {
+      view.pan += response.drag_delta();
+    }
+
+    // a texture bigger than the space can be panned until its edges, and a 
+    // smaller one stays centered
+    let image_size = TEXTURE_SIZE * scale;
+    let max_pan = ((image_size - rect.size()) / 2.).max(egui::Vec2::ZERO);
+    view.pan = view.pan.clamp(-max_pan, max_pan);
+
+    // line texture pixels up with screen pixels, so 100% is sharp
+    let image_min = ((rect.center() + view.pan - image_size / 2.).to_vec2() * pixels_per_point).round() / pixels_per_point;
+    let image_rect = egui::Rect::from_min_size(image_min.to_pos2(), image_size);
+    ui.painter_at(rect).image(egui_texture_id, image_rect,
+      egui::Rect::from_min_max(egui::pos2(0., 0.), egui::pos2(1., 1.)), egui::Color32::WHITE);
+
+    Some(SubViewportResponse {
+      response,
+      image_rect,
+      zoom: scale * pixels_per_point,
+    })
   }
 
   pub fn image_handle(&self) -> Handle<Image> {
@@ -0,0 +1,222 @@
+use std::ops::Range;
+
+use bevy::prelude::*;
+use hypher::{hyphenate, Lang};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::lyrics::{get_source_words, SYLLABLE_BREAK, TIMECODE_PATTERN};
+
+pub struct SyllablesPlugin;
+
+impl Plugin for SyllablesPlugin {
+  fn build(&self, app: &mut App) {
+    app.register_type::<SyllabificationSettings>();
+  }
+}
+
+#[derive(Serialize, Deserialize, Reflect, Clone, Default)]
+pub struct SyllabificationSettings {
+  /// Which language's hyphenation patterns to split words with.
+  pub language: SyllabificationLanguage,
+}
+
+#[derive(Serialize, Deserialize, Reflect, Clone, Copy, PartialEq, Default)]
+pub enum SyllabificationLanguage {
+  #[default]
+  English,
+  Dutch,
+  French,
+  German,
+  Italian,
+  Portuguese,
+  Spanish,
+  Swedish,
+}
+
+impl From<SyllabificationLanguage> for Lang {
+  fn from(language: SyllabificationLanguage) -> Self {
+    match language {
+      SyllabificationLanguage::English => Lang::English,
+      SyllabificationLanguage::Dutch => Lang::Dutch,
+      SyllabificationLanguage::French => Lang::French,
+      SyllabificationLanguage::German => Lang::German,
+      SyllabificationLanguage::Italian => Lang::Italian,
+      SyllabificationLanguage::Portuguese => Lang::Portuguese,
+      SyllabificationLanguage::Spanish => Lang::Spanish,
+      SyllabificationLanguage::Swedish => Lang::Swedish,
+    }
+  }
+}
+
+/// Splits every word of the untimed blocks into syllables. Returns the new lyrics and how many
+/// breaks were added.
+pub fn syllabify_lyrics(lyrics: &str, language: SyllabificationLanguage) -> (String, usize) {
+  let timecode_regex = Regex::new(TIMECODE_PATTERN).unwrap();
+  let mut break_count = 0;
+
+  let lines = lyrics.split('\n').collect::<Vec<_>>();
+  let mut new_lines = Vec::with_capacity(lines.len());
+  for block_lines in lines.split_inclusive(|line| line.trim().is_empty()) {
+    if block_lines.iter().any(|line| timecode_regex.is_match(line)) {
+      new_lines.extend(block_lines.iter().map(|line| line.to_string()));
+      continue;
+    }
+    for line in block_lines {
+      let (new_line, line_break_count) = syllabify_line(line, language.into());
+      new_lines.push(new_line);
+      break_count += line_break_count;
+    }
+  }
+
+  (new_lines.join("\n"), break_count)
+}
+
+fn syllabify_line(line: &str, lang: Lang) -> (String, usize) {
+  let mut new_line = String::with_capacity(line.len());
+  let mut break_count = 0;
+  let mut word = String::new();
+  // whether we're in a [tag] or {ruby}, which are copied as they are
+  let mut in_tag = false;
+  let mut in_ruby = false;
+
+  let mut flush_word = |word: &mut String, new_line: &mut String| {
+    if word.contains(SYLLABLE_BREAK) {
+      new_line.push_str(word);
+    } else {
+      let (syllabified, word_break_count) = syllabify_word(word, lang);
+      new_line.push_str(&syllabified);
+      break_count += word_break_count;
+    }
+    word.clear();
+  };
+
+  for c in line.chars() {
+    if in_tag || in_ruby {
+      new_line.push(c);
+      in_tag &= c != ']';
+      in_ruby &= c != '}';
+    } else if c == '[' || c == '{' || c.is_whitespace() {
+      flush_word(&mut word, &mut new_line);
+      new_line.push(c);
+      in_tag = c == '[';
+      in_ruby = c == '{';
+    } else {
+      word.push(c);
+    }
+  }
+  flush_word(&mut word, &mut new_line);
+
+  (new_line, break_count)
+}
+
+/// Hyphenates each run of letters in a word, leaving punctuation as it is.
+fn syllabify_word(word: &str, lang: Lang) -> (String, usize) {
+  let mut syllabified = String::with_capacity(word.len());
+  let mut break_count = 0;
+  let mut letters_start = None;
+  for (idx, c) in word.char_indices().chain(std::iter::once((word.len(), ' '))) {
+    match (letters_start, c.is_alphabetic()) {
+      (None, true) => letters_start = Some(idx),
+      (Some(start), false) => {
+        let syllables = hyphenate(&word[start..idx], lang).collect::<Vec<_>>();
+        break_count += syllables.len().saturating_sub(1);
+        syllabified.push_str(&syllables.join(SYLLABLE_BREAK.to_string().as_str()));
+        letters_start = None;
+      },
+      _ => {}
+    }
+    if letters_start.is_none() && idx < word.len() {
+      syllabified.push(c);
+    }
+  }
+  (syllabified, break_count)
+}
+
+/// Gets the byte range to replace with a timestamp for the next untimed syllable at or after
+/// `from`.
+pub fn find_next_syllable_start(lyrics: &str, from: usize) -> Option<Range<usize>> {
+  let timed_regex = Regex::new(&format!("{}$", TIMECODE_PATTERN)).unwrap();
+  let next_word_start = get_source_words(lyrics).into_iter()
+    .map(|word| word.start)
+    .find(|start| *start >= from && !timed_regex.is_match(&lyrics[..*start]))
+    .map(|start| start..start);
+  let next_break = get_syllable_breaks(lyrics).into_iter()
+    .find(|position| *position >= from)
+    .map(|position| position..(position + SYLLABLE_BREAK.len_utf8()));
+
+  match (next_word_start, next_break) {
+    (Some(word_start), Some(syllable_break)) => Some(if syllable_break.start < word_start.start {
+      syllable_break
+    } else {
+      word_start
+    }),
+    (word_start, syllable_break) => word_start.or(syllable_break),
+  }
+}
+
+/// Gets the positions of `|` syllable breaks, outside tags and ruby.
+pub fn get_syllable_breaks(lyrics: &str) -> Vec<usize> {
+  let mut breaks = Vec::new();
+  let mut in_tag = false;
+  let mut in_ruby = false;
+  for (idx, c) in lyrics.char_indices() {
+    if in_tag {
+      in_tag = c != ']';
+    } else if in_ruby {
+      in_ruby = c != '}';
+    } else if c == '[' {
+      in_tag = true;
+    } else if c == '{' {
+      in_ruby = true;
+    } else if c == SYLLABLE_BREAK {
+      breaks.push(idx);
+    }
+  }
+  breaks
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn syllabifies_untimed_blocks() {
+    let lyrics = "Beautiful wonderful\nsyllable\n\n[00:01.000]wonderful\nsyllable\n\nhello";
+    assert_eq!(syllabify_lyrics(lyrics, SyllabificationLanguage::English),
+      ("Beau|ti|ful won|der|ful\nsyl|la|ble\n\n[00:01.000]wonderful\nsyllable\n\nhello".to_string(), 6));
+  }
+
+  #[test]
+  fn leaves_tags_ruby_and_split_words_alone() {
+    let lyrics = "[singer=beautiful]{wonderful|won} syl|lable [section=chorus]beautiful";
+    assert_eq!(syllabify_lyrics(lyrics, SyllabificationLanguage::English),
+      ("[singer=beautiful]{wonderful|won} syl|lable [section=chorus]beau|ti|ful".to_string(), 2));
+  }
+
+  #[test]
+  fn keeps_punctuation_out_of_syllables() {
+    assert_eq!(syllabify_word("beautiful,", Lang::English), ("beau|ti|ful,".to_string(), 2));
+    assert_eq!(syllabify_word("\"Wonderful!\"", Lang::English), ("\"Won|der|ful!\"".to_string(), 2));
+    assert_eq!(syllabify_word("beautiful-wonderful", Lang::English), ("beau|ti|ful-won|der|ful".to_string(), 4));
+    assert_eq!(syllabify_word("don't", Lang::English), ("don't".to_string(), 0));
+  }
+
+  #[test]
+  fn finds_next_untimed_syllable() {
+    let lyrics = "[00:01.000]syl|la|ble wonderful";
+    // the first syllable is already timed
+    assert_eq!(find_next_syllable_start(lyrics, 0), Some(14..15));
+    assert_eq!(find_next_syllable_start(lyrics, 15), Some(17..18));
+    assert_eq!(find_next_syllable_start(lyrics, 18), Some(22..22));
+    assert_eq!(find_next_syllable_start(lyrics, 23), None);
+  }
+
+  #[test]
+  fn next_syllable_skips_ruby_and_tags() {
+    let lyrics = "{漢|かん} [color=red]x|y";
+    assert_eq!(find_next_syllable_start(lyrics, 0), Some(0..0));
+    assert_eq!(find_next_syllable_start(lyrics, 1), Some(24..24));
+    assert_eq!(find_next_syllable_start(lyrics, 25), Some(25..26));
+  }
+}
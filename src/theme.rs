@@ -0,0 +1,286 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use bevy_file_dialog::prelude::*;
+use directories::ProjectDirs;
+use serde::{Serialize, Deserialize};
+
+use crate::background::BackgroundSettings;
+use crate::editor::{show_and_log_error, show_and_log_info, show_and_log_warning, EditorState, FontState};
+use crate::project::{load_font, ProjectData};
+use crate::stage::{LyricLayout, LyricStyle, TitlecardSettings};
+
+pub struct ThemePlugin;
+
+impl Plugin for ThemePlugin {
+  fn build(&self, app: &mut App) {
+    app.insert_resource(ThemeLibrary::default());
+    app.insert_resource(ThemeDialog::default());
+    app.add_systems(Startup, load_theme_library);
+    app.add_systems(Update, handle_theme_import_dialog);
+    app.add_systems(Update, handle_theme_export_dialog);
+  }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Theme {
+  pub name: String,
+  pub background_color: Color,
+  pub sung_color: Color,
+  pub unsung_color: Color,
+  /// Only the file name in exported themes.
+  pub font_path: Option<PathBuf>,
+  pub style: LyricStyle,
+  pub layout: LyricLayout,
+  pub titlecard: TitlecardSettings,
+  /// `None` in themes saved before backgrounds were part of themes.
+  pub background: Option<BackgroundSettings>,
+}
+
+impl Theme {
+  pub fn from_project_data(name: String, project_data: &ProjectData) -> Self {
+    Self {
+      name,
+      background_color: project_data.background_color.unwrap_or_default(),
+      sung_color: project_data.sung_color.unwrap_or_default(),
+      unsung_color: project_data.unsung_color.unwrap_or_default(),
+      font_path: project_data.font_path.clone(),
+      style: project_data.style.clone().unwrap_or_default(),
+      layout: project_data.layout.clone().unwrap_or_default(),
+      titlecard: project_data.titlecard.clone().unwrap_or_default(),
+      background: Some(project_data.background.clone().unwrap_or_default()),
+    }
+  }
+
+  pub fn apply(&self, project_data: &mut ProjectData) {
+    project_data.background_color = Some(self.background_color);
+    project_data.sung_color = Some(self.sung_color);
+    project_data.unsung_color = Some(self.unsung_color);
+    project_data.font_path = self.font_path.clone();
+    project_data.style = Some(self.style.clone());
+    project_data.layout = Some(self.layout.clone());
+    project_data.titlecard = Some(self.titlecard.clone());
+    if let Some(background) = &self.background {
+      project_data.background = Some(background.clone());
+    }
+  }
+}
+
+#[derive(Resource, Default, Serialize, Deserialize)]
+pub struct ThemeLibrary {
+  pub themes: Vec<Theme>,
+}
+
+impl ThemeLibrary {
+  fn path() -> PathBuf {
+    ProjectDirs::from("", "yoteoke", "yoteoke").unwrap().config_dir().join("themes.json")
+  }
+
+  fn save(&self) -> Result<(), String> {
+    let path = Self::path();
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir).map_err(|e| format!("{:?}", e))?;
+    }
+    let serialized = serde_json::to_vec_pretty(self).map_err(|e| format!("{:?}", e))?;
+    fs::write(&path, serialized).map_err(|e| format!("{:?}", e))
+  }
+
+  /// Adds a theme, replacing any theme with the same name.
+  fn add(&mut self, theme: Theme) {
+    if let Some(existing) = self.themes.iter_mut().find(|existing| existing.name == theme.name) {
+      *existing = theme;
+    } else {
+      self.themes.push(theme);
+    }
+  }
+}
+
+fn load_theme_library(mut theme_library: ResMut<ThemeLibrary>) {
+  let path = ThemeLibrary::path();
+  let Ok(contents) = fs::read(&path) else {
+    // no themes saved yet
+    return;
+  };
+  match serde_json::from_slice::<ThemeLibrary>(&contents) {
+    Ok(library) => {
+      *theme_library = library;
+    },
+    Err(e) => {
+      error!("Couldn't read theme library {:?}: {:?}", path, e);
+    }
+  }
+}
+
+#[derive(Resource, Default)]
+pub struct ThemeDialog {
+  is_open: bool,
+  new_theme_name: String,
+  /// Font copied next to the theme being exported.
+  exported_font: Option<PathBuf>,
+}
+
+impl ThemeDialog {
+  pub fn open(&mut self) {
+    self.is_open = true;
+  }
+}
+
+pub struct ThemeImportDialog;
+
+pub struct ThemeExportDialog;
+
+pub fn configure_file_dialog_plugin(plugin: FileDialogPlugin) -> FileDialogPlugin {
+  plugin.with_load_file::<ThemeImportDialog>()
+    .with_save_file::<ThemeExportDialog>()
+}
+
+pub fn theme_dialog_ui(mut egui_contexts: EguiContexts,
+  mut theme_dialog: ResMut<ThemeDialog>,
+  mut theme_library: ResMut<ThemeLibrary>,
+  mut editor_state: NonSendMut<EditorState>,
+  mut fonts: ResMut<Assets<Font>>,
+  mut font_state: ResMut<FontState>,
+  mut commands: Commands
+) {
+  if !theme_dialog.is_open {
+    return;
+  }
+
+  let mut is_open = true;
+  let mut theme_to_save = None;
+  let mut theme_to_apply = None;
+  let mut theme_to_delete = None;
+  egui::Window::new("Themes").open(&mut is_open).show(egui_contexts.ctx_mut(), |ui| {
+    ui.horizontal(|ui| {
+      ui.label("Name");
+      ui.text_edit_singleline(&mut theme_dialog.new_theme_name);
+      let can_save = !theme_dialog.new_theme_name.trim().is_empty() && editor_state.project_data.is_some();
+      if ui.add_enabled(can_save, egui::Button::new("Save Current Project Style")).clicked() {
+        theme_to_save = Some(theme_dialog.new_theme_name.trim().to_string());
+      }
+    });
+
+    ui.separator();
+    if theme_library.themes.is_empty() {
+      ui.label("No saved themes");
+    }
+    egui::Grid::new("themes").striped(true).show(ui, |ui| {
+      for (idx, theme) in theme_library.themes.iter().enumerate() {
+        ui.label(&theme.name);
+        if ui.add_enabled(editor_state.project_data.is_some(), egui::Button::new("Apply")).clicked() {
+          theme_to_apply = Some(idx);
+        }
+        if ui.button("Export...").clicked() {
+          let exported = Theme {
+            font_path: theme.font_path.as_ref().and_then(|path| path.file_name()).map(PathBuf::from),
+            ..theme.clone()
+          };
+          theme_dialog.exported_font = theme.font_path.clone();
+          let serialized = serde_json::to_vec_pretty(&exported).unwrap();
+          commands.dialog().add_filter("YoteOke Theme", &["json"]).save_file::<ThemeExportDialog>(serialized);
+        }
+        if ui.button("Delete").clicked() {
+          theme_to_delete = Some(idx);
+        }
+        ui.end_row();
+      }
+    });
+
+    ui.separator();
+    if ui.button("Import...").clicked() {
+      commands.dialog().add_filter("YoteOke Theme", &["json"]).load_file::<ThemeImportDialog>();
+    }
+  });
+  theme_dialog.is_open = is_open;
+
+  let mut library_changed = false;
+  if let Some(name) = theme_to_save {
+    if let Some(project_data) = &editor_state.project_data {
+      theme_library.add(Theme::from_project_data(name, project_data));
+      library_changed = true;
+    }
+  }
+
+  if let Some(idx) = theme_to_delete {
+    theme_library.themes.remove(idx);
+    library_changed = true;
+  }
+
+  if let Some(idx) = theme_to_apply {
+    let mut theme = theme_library.themes[idx].clone();
+    if let Some(font_path) = theme.font_path.as_ref().filter(|font_path| !font_path.exists()) {
+      show_and_log_warning(editor_state.as_mut(),
+        format!("Font {:?} from theme {} wasn't found, so the default font is used", font_path, theme.name));
+      theme.font_path = None;
+    }
+    if let Some(project_data) = editor_state.project_data.as_mut() {
+      theme.apply(project_data);
+    }
+    font_state.lyric_font = None;
+    if let Some(font_path) = &theme.font_path {
+      font_state.lyric_font = load_font(font_path, fonts.as_mut(), editor_state.as_mut());
+    }
+    editor_state.needs_save_before_exit = true;
+    show_and_log_info(editor_state.as_mut(), format!("Applied theme {}", theme.name));
+  }
+
+  if library_changed {
+    if let Err(e) = theme_library.save() {
+      show_and_log_error(editor_state.as_mut(), format!("Error saving theme library: {}", e));
+    }
+  }
+}
+
+fn handle_theme_import_dialog(mut events: EventReader<DialogFileLoaded<ThemeImportDialog>>,
+  mut theme_library: ResMut<ThemeLibrary>,
+  mut editor_state: NonSendMut<EditorState>
+) {
+  for ev in events.read() {
+    match serde_json::from_slice::<Theme>(ev.contents.as_slice()) {
+      Ok(mut theme) => {
+        theme.font_path = theme.font_path.map(|font_path| resolve_font_path(font_path, &ev.path));
+        let name = theme.name.clone();
+        theme_library.add(theme);
+        match theme_library.save() {
+          Ok(_) => show_and_log_info(editor_state.as_mut(), format!("Imported theme {}", name)),
+          Err(e) => show_and_log_error(editor_state.as_mut(), format!("Error saving theme library: {}", e)),
+        }
+      },
+      Err(e) => {
+        show_and_log_error(editor_state.as_mut(), 
+          format!("Error reading theme {:?}: {:?}", ev.path, e));
+      }
+    }
+  }
+}
+
+/// Resolves a font file name against the imported theme's dir.
+fn resolve_font_path(font_path: PathBuf, theme_path: &Path) -> PathBuf {
+  if font_path.is_absolute() {
+    return font_path;
+  }
+  theme_path.parent().map(|dir| dir.join(&font_path)).unwrap_or(font_path)
+}
+
+fn handle_theme_export_dialog(mut events: EventReader<DialogFileSaved<ThemeExportDialog>>,
+  mut theme_dialog: ResMut<ThemeDialog>,
+  mut editor_state: NonSendMut<EditorState>
+) {
+  for ev in events.read() {
+    let font_path = theme_dialog.exported_font.take();
+    let copy_error = font_path.as_ref().and_then(|font_path| {
+      let copy_path = ev.path.with_file_name(font_path.file_name()?);
+      if copy_path == *font_path {
+        return None;
+      }
+      fs::copy(font_path, &copy_path).err().map(|e| (font_path, e))
+    });
+    match copy_error {
+      Some((font_path, e)) => show_and_log_warning(editor_state.as_mut(),
+        format!("Theme exported to {:?}, but its font {:?} couldn't be copied next to it: {:?}", ev.path, font_path, e)),
+      None => show_and_log_info(editor_state.as_mut(), format!("Theme exported to {:?}", ev.path)),
+    }
+  }
+}
@@ -2,12 +2,13 @@ use bevy::prelude::*;
 
 use std::time::Duration;
 use kira::sound::PlaybackState;
-use kira::Tween;
 use std::ops::RangeInclusive;
 
 use bevy_egui::egui;
 
-use crate::editor::{self, EditorState};
+use crate::editor::{self, show_and_log_error, show_and_log_info, EditorState};
+use crate::onsets::{self, DetectOnsetsRequestedEvent, OnsetState};
+use crate::beats::{self, BeatDetectionState, DetectBeatGridRequestedEvent};
 
 pub struct TimelinePlugin;
 
@@ -17,8 +18,15 @@ impl Plugin for TimelinePlugin {
   }
 }
 
+// seconds of song shown in the onset track
+const TRACK_WINDOW: f32 = 10.;
+
 pub fn timeline_ui(mut ui: InMut<egui::Ui>, mut editor_state: NonSendMut<EditorState>,
-  mut audio_state: NonSendMut<crate::editor::AudioState>
+  mut audio_state: NonSendMut<crate::editor::AudioState>,
+  onset_state: Res<OnsetState>,
+  mut detect_onsets_events: EventWriter<DetectOnsetsRequestedEvent>,
+  beat_detection_state: Res<BeatDetectionState>,
+  mut detect_beat_grid_events: EventWriter<DetectBeatGridRequestedEvent>
 ) {
   egui::TopBottomPanel::new(egui::panel::TopBottomSide::Top, "timeline_header").exact_height(48.).show_inside(&mut ui, |ui| {
     timeline_header_ui(ui, editor_state.reborrow(), audio_state.reborrow());
@@ -26,22 +34,27 @@ pub fn timeline_ui(mut ui: InMut<egui::Ui>, mut editor_state: NonSendMut<EditorS
 
   egui::CentralPanel::default().show_inside(&mut ui, |ui| {
     /*timeline_blocks_ui(ui, editor_state.reborrow());*/
+    timeline_beats_ui(ui, editor_state.reborrow(), audio_state.reborrow(), &beat_detection_state,
+      &mut detect_beat_grid_events);
+    timeline_onsets_ui(ui, editor_state.reborrow(), audio_state.reborrow(), &onset_state, 
+      &mut detect_onsets_events);
   });
 }
 
-fn seek_playhead_to(mut editor_state: Mut<EditorState>, mut audio_state: Mut<crate::editor::AudioState>, time: f64) {
+/// Seeks to `time` on the stage, including the song pre-delay.
+pub fn seek_playhead_to(mut editor_state: Mut<EditorState>, mut audio_state: Mut<crate::editor::AudioState>, time: f64) {
   if let Some(project_data) = &editor_state.project_data {
     let delay_time = project_data.song_delay_time.unwrap() as f64;
     let t = time - delay_time;
     if t < 0. {
       info!("seek_playhead_to: entering pre delay");
-      audio_state.music_handle.as_mut().unwrap().seek_to(0.);
+      audio_state.seek_to(0.);
       // pause the sound, we'll resume it after pre-delay
-      audio_state.music_handle.as_mut().unwrap().pause(Tween::default());
+      audio_state.pause();
       editor_state.is_in_pre_delay = true;
       editor_state.curr_pre_delay_time = time;
     } else {
-      audio_state.music_handle.as_mut().unwrap().seek_to(t);
+      audio_state.seek_to(t);
       editor_state.is_in_pre_delay = false;
       editor_state.curr_pre_delay_time = delay_time;
     }
@@ -69,14 +82,14 @@ pub fn play_buttons_ui(ui: &mut egui::Ui, mut editor_state: Mut<EditorState>,
     if editor_state.is_paused {
       if ui.button(">").clicked() {
         if !editor_state.is_in_pre_delay {
-          audio_state.music_handle.as_mut().unwrap().resume(Tween::default());
+          audio_state.resume();
         }
         editor_state.is_paused = false;
       }
     } else {
       if ui.button("||").clicked() {
         if !editor_state.is_in_pre_delay {
-          audio_state.music_handle.as_mut().unwrap().pause(Tween::default());
+          audio_state.pause();
         }
         editor_state.is_paused = true;
       }
@@ -86,7 +99,7 @@ pub fn play_buttons_ui(ui: &mut egui::Ui, mut editor_state: Mut<EditorState>,
     }
     if ui.button("->|").clicked() {
       if audio_state.music_handle.as_mut().unwrap().state() == PlaybackState::Playing {
-        audio_state.music_handle.as_mut().unwrap().pause(Tween::default());
+        audio_state.pause();
       }
       seek_playhead_to(editor_state.reborrow(), audio_state.reborrow(), total_time.as_secs_f64());
     }
@@ -114,20 +127,166 @@ pub fn timeline_header_ui(ui: &mut egui::Ui,
   let total_time_str = format!("{:0>2}:{:0>2}.{:0>3}", total_time.as_secs() / 60, total_time.as_secs() % 60, total_time.subsec_millis());
   egui::SidePanel::new(egui::panel::Side::Right, "timecode").show_inside(ui, |ui| {
     ui.label(format!("{} / {}", curr_time_str, total_time_str));
-    let volume_response = ui.add(egui::Slider::new(&mut audio_state.volume.0, RangeInclusive::new(-60., 0.)));
-    if volume_response.changed() {
-      let volume = audio_state.volume;
-      audio_state.music_handle.as_mut().unwrap().set_volume(volume, Tween::default());
-    }
+    ui.horizontal(|ui| {
+      let mut volumes_changed = ui.add(egui::Slider::new(&mut audio_state.volume.0, RangeInclusive::new(-60., 0.)))
+        .changed();
+      if audio_state.vocal_handle.is_some() {
+        volumes_changed |= ui.add(egui::Slider::new(&mut audio_state.vocal_level.0, RangeInclusive::new(-60., 0.))
+          .text("Vocal"))
+          .changed();
+        volumes_changed |= ui.checkbox(&mut audio_state.mute_instrumental, "Mute instrumental").changed();
+      }
+      if volumes_changed {
+        audio_state.apply_volumes();
+      }
+    });
   });
   egui::CentralPanel::default().show_inside(ui, |ui| {
     ui.style_mut().spacing.slider_width = ui.available_width();
     let mut mut_curr_time = curr_time.as_secs_f64();
     let slider_response = ui.add(egui::Slider::new(&mut mut_curr_time, RangeInclusive::new(0., total_time.as_secs_f64())).show_value(false));
     if slider_response.changed() {
-      audio_state.music_handle.as_mut().unwrap().seek_to(mut_curr_time);
+      audio_state.seek_to(mut_curr_time);
+    }
+  });
+}
+
+fn timeline_beats_ui(ui: &mut egui::Ui, mut editor_state: Mut<EditorState>,
+  mut audio_state: Mut<crate::editor::AudioState>, beat_detection_state: &BeatDetectionState,
+  detect_beat_grid_events: &mut EventWriter<DetectBeatGridRequestedEvent>
+) {
+  ui.horizontal(|ui| {
+    let detect_text = if beat_detection_state.is_detecting() { "Detecting BPM..." } else { "Detect BPM" };
+    if ui.add_enabled(!beat_detection_state.is_detecting(), egui::Button::new(detect_text)).clicked() {
+      detect_beat_grid_events.send_default();
+    }
+
+    let beat_grid = editor_state.project_data.as_ref()
+      .and_then(|project_data| project_data.beat_grid.clone());
+    if let Some(beat_grid) = &beat_grid {
+      ui.label(format!("{:.1} BPM", beat_grid.bpm));
+    }
+
+    if ui.add_enabled(beat_grid.is_some(), egui::Button::new("Quantize Timestamps to Beats")).clicked() {
+      if let (Some(project_data), Some(beat_grid)) = (editor_state.project_data.as_mut(), &beat_grid) {
+        let (lyrics, quantized_count) = beats::quantize_timestamps(&project_data.lyrics, beat_grid);
+        project_data.lyrics = lyrics;
+        editor_state.lyrics_dirty = true;
+        editor_state.needs_save_before_exit = true;
+        show_and_log_info(editor_state.as_mut(), format!("Quantized {} timestamps", quantized_count));
+      }
+    }
+
+    ui.checkbox(&mut audio_state.metronome_enabled, "Metronome");
+  });
+}
+
+fn timeline_onsets_ui(ui: &mut egui::Ui, mut editor_state: Mut<EditorState>,
+  mut audio_state: Mut<crate::editor::AudioState>, onset_state: &OnsetState,
+  detect_onsets_events: &mut EventWriter<DetectOnsetsRequestedEvent>
+) {
+  let song_time = audio_state.playhead_position().as_secs_f32();
+
+  ui.horizontal(|ui| {
+    let detect_text = if onset_state.is_analyzing() { "Detecting Onsets..." } else { "Detect Onsets" };
+    if ui.add_enabled(!onset_state.is_analyzing(), egui::Button::new(detect_text)).clicked() {
+      detect_onsets_events.send_default();
+    }
+
+    let has_onsets = !onset_state.onsets.is_empty();
+    if ui.add_enabled(has_onsets, egui::Button::new("Snap Timestamps to Onsets")).clicked() {
+      if let Some(project_data) = editor_state.project_data.as_mut() {
+        let (lyrics, snapped_count) = onsets::snap_timestamps_to_onsets(&project_data.lyrics, onset_state);
+        project_data.lyrics = lyrics;
+        editor_state.lyrics_dirty = true;
+        editor_state.needs_save_before_exit = true;
+        show_and_log_info(editor_state.as_mut(), format!("Snapped {} timestamps to onsets", snapped_count));
+      }
+    }
+
+    if ui.add_enabled(has_onsets, egui::Button::new("Auto-Distribute Current Block"))
+      .on_hover_text("Times each word of the block at the playhead to an onset between its first and last timestamps")
+      .clicked() 
+    {
+      let result = match (editor_state.parsed_lyrics.as_ref(), editor_state.project_data.as_ref()) {
+        (Some(parsed_lyrics), Some(project_data)) => {
+          let song_position = Duration::from_secs_f32(song_time);
+          parsed_lyrics.get_block_sung_at(&song_position)
+            .ok_or("No block at the playhead".to_string())
+            .and_then(|block| onsets::distribute_block_over_onsets(&project_data.lyrics, block, onset_state))
+        },
+        _ => Err("No lyrics".to_string()),
+      };
+      match result {
+        Ok(lyrics) => {
+          editor_state.project_data.as_mut().unwrap().lyrics = lyrics;
+          editor_state.lyrics_dirty = true;
+          editor_state.needs_save_before_exit = true;
+        },
+        Err(e) => show_and_log_error(editor_state.as_mut(), format!("Couldn't distribute block: {}", e)),
+      }
     }
   });
+
+  let (response, painter) = ui.allocate_painter(egui::vec2(ui.available_width(), 48.), egui::Sense::click());
+  let rect = response.rect;
+  painter.rect_filled(rect, 2., ui.visuals().extreme_bg_color);
+
+  let window_start = song_time - TRACK_WINDOW / 2.;
+  let time_to_x = |time: f32| rect.left() + (time - window_start) / TRACK_WINDOW * rect.width();
+  let in_window = |time: f32| (window_start..window_start + TRACK_WINDOW).contains(&time);
+
+  let beat_grid = editor_state.project_data.as_ref()
+    .and_then(|project_data| project_data.beat_grid.clone())
+    .filter(|beat_grid| beat_grid.show_grid);
+  if let Some(beat_grid) = beat_grid {
+    let first_beat = beat_grid.get_beat_at(window_start).ceil() as i64;
+    let last_beat = beat_grid.get_beat_at(window_start + TRACK_WINDOW).floor() as i64;
+    for beat in first_beat..=last_beat {
+      let x = time_to_x(beat_grid.get_beat_time(beat as f32));
+      let color = if beat_grid.is_downbeat(beat) { egui::Color32::GRAY } else { egui::Color32::DARK_GRAY };
+      painter.line_segment([egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())], egui::Stroke::new(1., color));
+    }
+  }
+
+  let max_strength = onset_state.onsets.iter().map(|onset| onset.strength).fold(f32::EPSILON, f32::max);
+  for onset in onset_state.onsets.iter().filter(|onset| in_window(onset.time)) {
+    let height = rect.height() * (0.3 + 0.7 * onset.strength / max_strength);
+    let x = time_to_x(onset.time);
+    painter.line_segment([egui::pos2(x, rect.bottom()), egui::pos2(x, rect.bottom() - height)],
+      egui::Stroke::new(1., egui::Color32::LIGHT_BLUE));
+  }
+
+  if let Some(parsed_lyrics) = &editor_state.parsed_lyrics {
+    for timestamp in parsed_lyrics.blocks.iter().flat_map(|block| block.timestamps.iter()) {
+      let time = timestamp.time.as_secs_f32();
+      if in_window(time) {
+        let x = time_to_x(time);
+        // syllable ends are shorter, so gaps stand out
+        let (bottom, color) = if timestamp.is_syllable_end {
+          (rect.top() + rect.height() * 0.25, egui::Color32::from_rgb(255, 140, 0))
+        } else {
+          (rect.center().y, egui::Color32::YELLOW)
+        };
+        painter.line_segment([egui::pos2(x, rect.top()), egui::pos2(x, bottom)],
+          egui::Stroke::new(2., color));
+      }
+    }
+  }
+
+  let playhead_x = time_to_x(song_time);
+  painter.line_segment([egui::pos2(playhead_x, rect.top()), egui::pos2(playhead_x, rect.bottom())],
+    egui::Stroke::new(1., egui::Color32::WHITE));
+
+  if response.clicked() {
+    if let Some(pointer_pos) = response.interact_pointer_pos() {
+      let time = window_start + (pointer_pos.x - rect.left()) / rect.width() * TRACK_WINDOW;
+      let delay_time = editor_state.project_data.as_ref()
+        .and_then(|project_data| project_data.song_delay_time)
+        .unwrap_or_default();
+      seek_playhead_to(editor_state.reborrow(), audio_state.reborrow(), (time.max(0.) + delay_time) as f64);
+    }
+  }
 }
 
 fn timeline_blocks_ui(ui: &mut egui::Ui, mut editor_state: Mut<EditorState>,) {
@@ -162,7 +321,7 @@ fn handle_pre_delay(mut editor_state: NonSendMut<EditorState>,
     if editor_state.curr_pre_delay_time > delay_time {
       info!("handle_pre_delay: exiting pre delay");
       editor_state.curr_pre_delay_time = delay_time;
-      audio_state.music_handle.as_mut().unwrap().resume(Tween::default());
+      audio_state.resume();
       editor_state.is_paused = false;
       editor_state.is_in_pre_delay = false;
     }
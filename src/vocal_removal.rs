@@ -0,0 +1,252 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use bevy_tokio_tasks::TokioTasksRuntime;
+use kira::sound::static_sound::StaticSoundData;
+use serde::{Deserialize, Serialize};
+
+use crate::audio::Biquad;
+use crate::editor::{show_and_log_error, show_and_log_info, EditorState};
+
+pub struct VocalRemovalPlugin;
+
+impl Plugin for VocalRemovalPlugin {
+  fn build(&self, app: &mut App) {
+    app.add_event::<GenerateInstrumentalRequestedEvent>();
+    app.add_systems(Update, handle_generate_instrumental_requested);
+    app.insert_resource(VocalRemovalState::default());
+    app.register_type::<VocalRemovalSettings>();
+  }
+}
+
+/// Settings for generating an instrumental from the song.
+#[derive(Serialize, Deserialize, Reflect, Clone)]
+pub struct VocalRemovalSettings {
+  /// Center content below this, in Hz, is kept for the bass.
+  pub bass_preserve_cutoff: f32,
+  /// Exports the generated instrumental instead of the song file.
+  pub use_for_export: bool,
+}
+
+impl Default for VocalRemovalSettings {
+  fn default() -> Self {
+    Self {
+      bass_preserve_cutoff: 120.,
+      use_for_export: false,
+    }
+  }
+}
+
+#[derive(Event, Default)]
+pub struct GenerateInstrumentalRequestedEvent;
+
+#[derive(Resource, Default)]
+pub struct VocalRemovalState {
+  is_generating: bool,
+}
+
+impl VocalRemovalState {
+  pub fn is_generating(&self) -> bool {
+    self.is_generating
+  }
+}
+
+fn handle_generate_instrumental_requested(
+  mut events: EventReader<GenerateInstrumentalRequestedEvent>,
+  mut editor_state: NonSendMut<EditorState>,
+  mut vocal_removal_state: ResMut<VocalRemovalState>,
+  tokio_runtime: Res<TokioTasksRuntime>
+) {
+  for _ in events.read() {
+    if vocal_removal_state.is_generating {
+      continue;
+    }
+
+    let Some(project_data) = editor_state.project_data.as_ref() else {
+      continue;
+    };
+    let Some(song_file) = project_data.song_file.clone() else {
+      show_and_log_error(editor_state.as_mut(), "Can't generate an instrumental without a song file".into());
+      continue;
+    };
+    let settings = project_data.vocal_removal.clone().unwrap_or_default();
+    let output_path = get_instrumental_path(&editor_state.project_file_path, &song_file);
+
+    vocal_removal_state.is_generating = true;
+    show_and_log_info(editor_state.as_mut(), format!("Generating instrumental from {:?}...", song_file));
+
+    tokio_runtime.spawn_background_task(|mut ctx| async move {
+      let result = StaticSoundData::from_file(&song_file)
+        .map_err(|e| format!("{:?}", e))
+        .and_then(|sound_data| {
+          let frames = cancel_center_channel(&sound_data, settings.bass_preserve_cutoff);
+          write_wav(&output_path, sound_data.sample_rate, &frames)
+            .map_err(|e| format!("{:?}", e))
+        });
+
+      ctx.run_on_main_thread(move |ctx| {
+        ctx.world.resource_mut::<VocalRemovalState>().is_generating = false;
+
+        let mut editor_state = ctx.world.get_non_send_resource_mut::<EditorState>().unwrap();
+        match result {
+          Ok(_) => {
+            if let Some(project_data) = editor_state.project_data.as_mut() {
+              project_data.instrumental_file = Some(output_path.clone());
+            }
+            editor_state.needs_save_before_exit = true;
+            show_and_log_info(editor_state.as_mut(), format!("Instrumental saved to {:?}", output_path));
+          },
+          Err(e) => show_and_log_error(editor_state.as_mut(),
+            format!("Error generating instrumental from {:?}: {}", song_file, e)),
+        }
+      }).await;
+    });
+  }
+}
+
+/// Next to the project file, or the song if the project hasn't been saved.
+fn get_instrumental_path(project_file_path: &Path, song_file: &Path) -> PathBuf {
+  let dir = project_file_path.parent()
+    .filter(|dir| !dir.as_os_str().is_empty())
+    .or(song_file.parent())
+    .unwrap_or(Path::new("."));
+  let song_name = song_file.file_stem()
+    .map(|stem| stem.to_string_lossy().into_owned())
+    .unwrap_or("song".into());
+  dir.join(format!("{}_instrumental.wav", song_name))
+}
+
+/// Removes the center of the stereo image, keeping it below `cutoff` Hz.
+fn cancel_center_channel(sound_data: &StaticSoundData, cutoff: f32) -> Vec<[f32; 2]> {
+  let mut bass_filter = Biquad::low_pass(cutoff, sound_data.sample_rate as f32);
+  sound_data.frames.iter()
+    .map(|frame| {
+      let mid = (frame.left + frame.right) / 2.;
+      let side = (frame.left - frame.right) / 2.;
+      let bass = bass_filter.process(mid);
+      [bass + side, bass - side]
+    })
+    .collect()
+}
+
+/// Writes 16-bit stereo PCM to a WAV file.
+fn write_wav(path: &Path, sample_rate: u32, frames: &[[f32; 2]]) -> std::io::Result<()> {
+  const CHANNELS: u16 = 2;
+  const BITS_PER_SAMPLE: u16 = 16;
+  let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+  let data_len = (frames.len() * block_align as usize) as u32;
+
+  let mut bytes = Vec::with_capacity(44 + data_len as usize);
+  bytes.extend_from_slice(b"RIFF");
+  bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+  bytes.extend_from_slice(b"WAVE");
+  bytes.extend_from_slice(b"fmt ");
+  bytes.extend_from_slice(&16u32.to_le_bytes());
+  // pcm
+  bytes.extend_from_slice(&1u16.to_le_bytes());
+  bytes.extend_from_slice(&CHANNELS.to_le_bytes());
+  bytes.extend_from_slice(&sample_rate.to_le_bytes());
+  bytes.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+  bytes.extend_from_slice(&block_align.to_le_bytes());
+  bytes.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+  bytes.extend_from_slice(b"data");
+  bytes.extend_from_slice(&data_len.to_le_bytes());
+  for frame in frames {
+    for sample in frame {
+      let sample = (sample.clamp(-1., 1.) * i16::MAX as f32) as i16;
+      bytes.extend_from_slice(&sample.to_le_bytes());
+    }
+  }
+
+  std::fs::File::create(path)?.write_all(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use kira::sound::static_sound::StaticSoundSettings;
+  use kira::Frame;
+
+  use super::*;
+
+  const SAMPLE_RATE: u32 = 44100;
+
+  /// Makes half a second of stereo audio from a function of time.
+  fn make_sound(samples: impl Fn(f32) -> (f32, f32)) -> StaticSoundData {
+    let frames = (0..SAMPLE_RATE / 2)
+      .map(|idx| {
+        let (left, right) = samples(idx as f32 / SAMPLE_RATE as f32);
+        Frame { left, right }
+      })
+      .collect::<Arc<[Frame]>>();
+    StaticSoundData {
+      sample_rate: SAMPLE_RATE,
+      frames,
+      settings: StaticSoundSettings::default(),
+      slice: None,
+    }
+  }
+
+  fn sine(frequency: f32, t: f32) -> f32 {
+    (t * frequency * std::f32::consts::TAU).sin() * 0.5
+  }
+
+  /// Gets the loudest sample in the second half, after the filter has settled.
+  fn peak(frames: &[[f32; 2]]) -> f32 {
+    frames[(frames.len() / 2)..].iter().flatten().fold(0., |peak, sample| f32::max(peak, sample.abs()))
+  }
+
+  #[test]
+  fn removes_center() {
+    let frames = cancel_center_channel(&make_sound(|t| (sine(2000., t), sine(2000., t))), 120.);
+    assert!(peak(&frames) < 0.01);
+  }
+
+  #[test]
+  fn keeps_sides() {
+    let sound_data = make_sound(|t| (sine(2000., t), -sine(2000., t)));
+    let frames = cancel_center_channel(&sound_data, 120.);
+    for (frame, input) in frames.iter().zip(sound_data.frames.iter()) {
+      assert_eq!(*frame, [input.left, input.right]);
+    }
+  }
+
+  #[test]
+  fn keeps_center_bass() {
+    let frames = cancel_center_channel(&make_sound(|t| (sine(50., t), sine(50., t))), 120.);
+    assert!(peak(&frames) > 0.45);
+  }
+
+  #[test]
+  fn writes_wav_header_and_samples() {
+    let path = std::env::temp_dir().join(format!("yoteoke_write_wav_test_{}.wav", std::process::id()));
+    let frames = [[0., 0.], [0.5, -0.5], [2., -2.]];
+    write_wav(&path, 48000, &frames).unwrap();
+    let bytes = std::fs::read(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    let u16_at = |idx: usize| u16::from_le_bytes([bytes[idx], bytes[idx + 1]]);
+    let u32_at = |idx: usize| u32::from_le_bytes([bytes[idx], bytes[idx + 1], bytes[idx + 2], bytes[idx + 3]]);
+    assert_eq!(&bytes[0..4], b"RIFF");
+    assert_eq!(u32_at(4) as usize, bytes.len() - 8);
+    assert_eq!(&bytes[8..16], b"WAVEfmt ");
+    assert_eq!(u32_at(16), 16);
+    // pcm, stereo
+    assert_eq!((u16_at(20), u16_at(22)), (1, 2));
+    assert_eq!(u32_at(24), 48000);
+    // bytes per second and per frame
+    assert_eq!(u32_at(28), 48000 * 4);
+    assert_eq!(u16_at(32), 4);
+    assert_eq!(u16_at(34), 16);
+    assert_eq!(&bytes[36..40], b"data");
+    assert_eq!(u32_at(40) as usize, frames.len() * 4);
+    assert_eq!(bytes.len(), 44 + frames.len() * 4);
+
+    let samples = bytes[44..].chunks(2)
+      .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+      .collect::<Vec<_>>();
+    assert_eq!(samples, vec![0, 0, 16383, -16383, i16::MAX, -i16::MAX]);
+  }
+}